@@ -1,12 +1,21 @@
 // src/main.rs
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use fs2::FileExt;
+use fuzzy_matcher::clangd::ClangdMatcher;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use jwalk::{Parallelism, WalkDir};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use walkdir::WalkDir;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 #[derive(Parser)]
 #[command(name = "gcd")]
@@ -17,245 +26,4827 @@ struct Cli {
 
     /// Pattern to match repository name (when no subcommand is provided)
     pattern: Option<String>,
+
+    /// Print the repository listing as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Minimum fuzzy score a repository needs to be considered a match
+    #[arg(long)]
+    min_score: Option<i64>,
+
+    /// Drop candidates whose name fuzzy-matches this pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Only consider repositories whose checked-out branch fuzzy-matches
+    /// this, as recorded by `gcd index --branches`; without a pattern, go to
+    /// the repository whose branch matches best
+    #[arg(long, value_name = "PATTERN")]
+    branch: Option<String>,
+
+    /// What to do when several repositories match (overrides `resolution`
+    /// in the config)
+    #[arg(long, value_enum)]
+    resolution: Option<Resolution>,
+
+    /// Number of candidates shown per page of the selection menu (overrides
+    /// `max_results` in the config; defaults to 20)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    max_results: Option<u16>,
+
+    /// Also match the pattern against the full repository path (implied when
+    /// the pattern contains a `/`)
+    #[arg(long)]
+    path: bool,
+
+    /// Look the pattern up as an exact repository name instead of fuzzy matching
+    #[arg(long, conflicts_with_all = ["regex", "glob"])]
+    exact: bool,
+
+    /// Treat the pattern as a regular expression matched against repository names
+    #[arg(long, conflicts_with = "glob")]
+    regex: bool,
+
+    /// Treat the pattern as a shell-style glob matched against repository names
+    #[arg(long)]
+    glob: bool,
+
+    /// Match the case of the pattern exactly instead of ignoring it (can
+    /// also be enabled with `case_sensitive` in the config)
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Fuzzy matching algorithm (overrides `matcher` in the config)
+    #[arg(long, value_enum)]
+    matcher: Option<MatcherKind>,
+
+    /// Break score ties in favour of the most recently modified repository
+    /// directory (can also be enabled with `prefer_recent` in the config)
+    #[arg(long)]
+    recent: bool,
+
+    /// When nothing matches, rescan the indexed directories once and try
+    /// again (can also be enabled with `auto_index` in the config)
+    #[arg(long)]
+    auto_index: bool,
+
+    /// Print every candidate and its score to stderr before navigating
+    #[arg(long)]
+    debug_score: bool,
+
+    /// Only consider the first N matches or listed repositories
+    #[arg(long, global = true, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Terminate each printed path or listing entry with a NUL byte instead
+    /// of a newline, for use with `xargs -0`
+    #[arg(long, global = true)]
+    print0: bool,
+
+    /// Use the repositories of a separate profile instead of the default one
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Only consider or list repositories carrying this tag
+    #[arg(long, global = true)]
+    tag: Option<String>,
+
+    /// Print progress and diagnostics to stderr, and scan roots in `gcd list`
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Don't print informational messages such as "Indexed N repositories"
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Start with an empty index if the config can't be parsed (it is
+    /// backed up first)
+    #[arg(long, global = true)]
+    reset_config: bool,
+
+    /// Never write the config or its lock file, e.g. on a read-only file
+    /// system. Visits aren't recorded and commands that change the index
+    /// fail (also enabled by setting `GCD_NO_CONFIG_WRITE`)
+    #[arg(long, global = true)]
+    no_config_write: bool,
+
+    /// Print paths relative to BASE (the current directory if none is given)
+    /// when navigating or listing, e.g. `--relative=../..`
+    #[arg(long, global = true, value_name = "BASE", num_args = 0..=1, require_equals = true, default_missing_value = ".")]
+    relative: Option<PathBuf>,
+
+    /// Reuse the matches found for the same pattern and flags by earlier
+    /// runs until the config changes, for completion scripts that call
+    /// `gcd` many times in a row
+    #[arg(long, global = true)]
+    cache_matches: bool,
+
+    /// Print the names of all indexed repositories, one per line
+    #[arg(long, hide = true)]
+    list_names: bool,
+
+    /// Print the repositories matching the pattern (or all of them) as
+    /// `name<TAB>path` lines, for an external picker such as fzf
+    #[arg(long, conflicts_with = "resolve_name")]
+    candidates: bool,
+
+    /// Go to the repository with exactly this name, as picked by an external
+    /// tool. Reads the name from stdin when omitted; anything after a tab is
+    /// ignored, so `--candidates` lines can be passed back unchanged
+    #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = "-", conflicts_with = "pattern")]
+    resolve_name: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Index repositories in the specified directory
+    /// Index repositories in the specified directories
     Index {
-        /// Directory to scan for git repositories
+        /// Directories to scan for git repositories
         #[arg(default_value = ".")]
-        path: PathBuf,
+        paths: Vec<PathBuf>,
+
+        /// Maximum directory depth to descend, measured from each scan root
+        /// (a repository exactly at this depth is still detected)
+        #[arg(long)]
+        depth: Option<usize>,
+
+        /// Additional directory name to skip while scanning (repeatable)
+        #[arg(long = "ignore", value_name = "DIR")]
+        ignore: Vec<String>,
+
+        /// Do not follow symbolic links while scanning
+        #[arg(long)]
+        no_follow_links: bool,
+
+        /// Number of threads used to scan (defaults to the number of logical CPUs)
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// Name repositories after their `origin` remote instead of their directory
+        #[arg(long)]
+        name_from_remote: bool,
+
+        /// Name repositories after the last N components of their path, e.g.
+        /// `teamA/frontend` for N = 2
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        name_from: u16,
+
+        /// Read repository paths from stdin, one per line, instead of scanning
+        #[arg(long, conflicts_with = "paths")]
+        stdin: bool,
+
+        /// List the repositories that would be indexed without saving them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Re-read the metadata of every repository, even ones unchanged since the last scan
+        #[arg(long)]
+        full: bool,
+
+        /// Check that git can read each repository, reporting and skipping broken ones
+        #[arg(long)]
+        check: bool,
+
+        /// Only add repositories that aren't indexed yet, leaving existing entries untouched
+        #[arg(long)]
+        new_only: bool,
+
+        /// Only index repositories whose `origin` URL contains this text
+        #[arg(long, value_name = "TEXT")]
+        remote_filter: Option<String>,
+
+        /// Don't descend into directories whose name starts with `.`, such as
+        /// `.cache` (repositories that are themselves hidden are skipped too)
+        #[arg(long)]
+        no_hidden: bool,
+
+        /// Record the branch each repository has checked out, for `--branch`
+        /// (always on with `index_branches` in the config)
+        #[arg(long)]
+        branches: bool,
+
+        /// Also ask git for each repository's submodules and index their
+        /// working directories, including ones the scan doesn't reach
+        #[arg(long)]
+        include_submodules: bool,
+    },
+    /// Add a single repository to the index without scanning
+    Add {
+        /// Path to the repository (defaults to the current directory)
+        path: Option<PathBuf>,
+
+        /// Name to store the repository under (defaults to the directory name);
+        /// a repository already indexed under another name is renamed
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Overwrite a different repository already stored under `--name`
+        #[arg(long, requires = "name")]
+        force: bool,
+    },
+    /// Write the index to a portable JSON file for use on another machine
+    Export {
+        /// File to write to (defaults to stdout)
+        #[arg(long)]
+        out: Option<PathBuf>,
+
+        /// Include each repository's path, not just its name and remote
+        #[arg(long)]
+        paths: bool,
+    },
+    /// Merge repositories from a file written by `gcd export`, or from
+    /// another tool's list of directories
+    Import {
+        /// File to read
+        #[arg(required_unless_present = "from")]
+        file: Option<PathBuf>,
+
+        /// Import the repositories tracked by another tool instead of a file
+        #[arg(long, value_enum, conflicts_with = "file")]
+        from: Option<ImportSource>,
+
+        /// Replace the path prefix FROM with TO in imported paths, e.g.
+        /// `/home/me=/Users/me` (repeatable; the longest matching FROM wins)
+        #[arg(long, value_name = "FROM=TO", value_parser = parse_path_rewrite, conflicts_with = "from")]
+        rewrite: Vec<PathRewrite>,
+    },
+    /// Change the name a repository is indexed under
+    Rename {
+        /// Current name of the repository
+        old: String,
+
+        /// New name for the repository
+        new: String,
+
+        /// Overwrite an existing repository with the new name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Re-scan all previously indexed directories
+    Reindex {
+        /// Re-read the metadata of every repository, even ones unchanged since the last scan
+        #[arg(long)]
+        full: bool,
+
+        /// Record the branch each repository has checked out, for `--branch`
+        #[arg(long)]
+        branches: bool,
+    },
+    /// Remove repositories whose directories no longer exist
+    Clean {
+        /// List stale entries without removing them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Also remove repositories not visited within this long, e.g. 30d or 6w
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: Option<Duration>,
+    },
+    /// Merge repositories indexed more than once under different names into
+    /// one entry, keeping their visits, aliases, hooks and tags
+    PruneDuplicates {
+        /// Which name to keep for each repository (asks for each one if not given)
+        #[arg(long, value_enum)]
+        keep: Option<KeepName>,
+
+        /// List the duplicates without merging them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the root of the git repository containing the current directory
+    Root,
+    /// Show how often each repository has been navigated to
+    Stats,
+    /// Print the path of the config file in use
+    ConfigPath,
+    /// Check the config, the index and the shell integration for problems
+    Doctor,
+    /// Describe the supported subcommands, flags and formats as JSON, for
+    /// wrappers that adapt to the installed version
+    #[command(name = "__capabilities", hide = true)]
+    Capabilities,
+    /// Measure how long parts of gcd take, without changing the config
+    #[command(hide = true)]
+    Bench {
+        #[command(subcommand)]
+        target: BenchTarget,
+    },
+    /// Print shell integration to stdout instead of editing an rc file,
+    /// e.g. `eval "$(gcd init zsh)"`
+    Init {
+        /// Shell to generate integration for (bash, zsh, fish, ps, nu, xonsh)
+        shell: String,
+
+        /// How to enter the matched repository
+        #[arg(long, value_enum, default_value_t = IntegrationMode::Cd)]
+        mode: IntegrationMode,
+
+        /// Name of the shell function to define; the binary is still run as `gcd`
+        #[arg(long, value_name = "NAME", default_value = "gcd", value_parser = parse_command_name)]
+        command_name: String,
+    },
+    /// Remove shell integration
+    Uninstall {
+        /// Shell to remove integration for (bash, zsh, fish, ps, nu, xonsh)
+        shell: String,
+
+        /// Modify the rc file without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Generate shell completions
+    Completions {
+        /// Shell to generate completions for (bash, zsh, fish, ps)
+        shell: String,
     },
     /// Install shell integration
     Install {
-        /// Shell to install for (bash, zsh, fish, ps)
-        #[arg(default_value = "bash")]
-        shell: String,
+        /// Shell to install for (bash, zsh, fish, ps, nu, xonsh); detected
+        /// from the environment when omitted
+        shell: Option<String>,
+
+        /// How to enter the matched repository
+        #[arg(long, value_enum, default_value_t = IntegrationMode::Cd)]
+        mode: IntegrationMode,
+
+        /// Modify the rc file without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Name of the shell function to define; the binary is still run as `gcd`
+        #[arg(long, value_name = "NAME", default_value = "gcd", value_parser = parse_command_name)]
+        command_name: String,
+    },
+    /// List indexed repositories in a stable order
+    List {
+        /// Only show repositories whose name contains this text
+        filter: Option<String>,
+
+        /// Field to sort by
+        #[arg(long, value_enum, default_value_t = ListSort::Name)]
+        sort: ListSort,
+
+        /// Print each repository using a template such as "{name}\t{path}".
+        /// Placeholders: {name}, {path}, {parent}, {visits}, {branch} (the
+        /// default branch), {remote}, {tags}
+        #[arg(long, value_parser = parse_list_format)]
+        format: Option<ListFormat>,
+    },
+    /// Open the best matching repository in an editor or file manager
+    Open {
+        /// Pattern to match repository name
+        pattern: String,
+    },
+    /// Make an extra name resolve to an existing repository
+    Alias {
+        /// The new name
+        alias: String,
+
+        /// Name of the repository it should resolve to
+        target: String,
+    },
+    /// Tag a repository, or list its tags when none are given
+    Tag {
+        /// Name of the repository
+        name: String,
+
+        /// Tags to add
+        tags: Vec<String>,
+
+        /// Remove the given tags instead of adding them
+        #[arg(long, requires = "tags")]
+        remove: bool,
+    },
+    /// Print the repository a pattern resolves to without recording a visit
+    Which {
+        /// Pattern to match repository name
+        pattern: String,
+
+        /// Print every candidate with its score instead of only the best one
+        #[arg(long)]
+        all: bool,
+    },
+    /// Remove a repository from the index
+    Remove {
+        /// Name or alias of the repository to remove; other patterns are
+        /// fuzzy matched and the match confirmed before it is removed
+        name: String,
+
+        /// Remove a fuzzy match without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
     },
 }
 
-#[derive(Serialize, Deserialize, Default)]
-struct Config {
-    repos: HashMap<String, PathBuf>,
+#[derive(Subcommand)]
+enum BenchTarget {
+    /// Time repository discovery under a directory
+    Index {
+        /// Directory to scan
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Number of threads used to scan
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// Ignore the scan cache, treating every repository as changed
+        #[arg(long)]
+        full: bool,
+    },
 }
 
-impl Config {
-    fn load() -> Self {
-        let config_path = config_path();
-        if config_path.exists() {
-            let contents = std::fs::read_to_string(config_path).unwrap_or_default();
-            serde_json::from_str(&contents).unwrap_or_default()
-        } else {
-            Config {
-                repos: HashMap::new(),
+/// Tools whose list of directories `gcd import --from` understands.
+#[derive(Clone, Copy, ValueEnum)]
+enum ImportSource {
+    /// Repositories cloned with ghq (`ghq list -p`)
+    Ghq,
+    /// Directories known to zoxide (`zoxide query -l`), keeping only git repositories
+    Zoxide,
+}
+
+impl ImportSource {
+    /// The command that prints the tool's directories, one per line.
+    fn command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            ImportSource::Ghq => ("ghq", &["list", "-p"]),
+            ImportSource::Zoxide => ("zoxide", &["query", "-l"]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ListSort {
+    Name,
+    Path,
+    Visits,
+    /// Most recently added first
+    Added,
+}
+
+/// Which of several names for the same repository `gcd prune-duplicates`
+/// keeps.
+#[derive(Clone, Copy, ValueEnum)]
+enum KeepName {
+    Shortest,
+    Longest,
+    /// The name the repository was first added under
+    First,
+}
+
+/// How navigation picks between several matching repositories. Without
+/// one, a menu is shown when run directly in a terminal and the best match
+/// is used when the output is captured.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Resolution {
+    /// Always use the best match
+    First,
+    /// Show a menu whenever a terminal is available, even if the output is captured
+    Interactive,
+    /// Fail, listing the candidates on stderr
+    Error,
+}
+
+/// The fuzzy matching algorithm used to score repositories.
+#[derive(Clone, Copy, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MatcherKind {
+    /// The skim algorithm, favouring matches at word boundaries
+    #[default]
+    Skim,
+    /// The algorithm used by clangd's code completion
+    Clangd,
+}
+
+/// The file format written by `gcd export` and read by `gcd import`.
+#[derive(Serialize, Deserialize)]
+struct ExportedIndex {
+    repos: Vec<ExportedRepo>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedRepo {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    remote: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RepoListing<'a> {
+    name: &'a str,
+    #[serde(serialize_with = "serialize_os_path")]
+    path: Cow<'a, Path>,
+}
+
+/// What `gcd __capabilities` reports. Fields are only ever added, so that
+/// wrappers can rely on the ones they know about.
+#[derive(Serialize)]
+struct Capabilities {
+    version: &'static str,
+    /// Bumped when the config layout changes in a way older versions can't read
+    config_version: u32,
+    config_formats: &'static [&'static str],
+    integration_version: u32,
+    shells: &'static [&'static str],
+    /// Long flags accepted by every invocation
+    flags: Vec<String>,
+    /// Long flags accepted by each visible subcommand, keyed by its name
+    subcommands: std::collections::BTreeMap<String, Vec<String>>,
+}
+
+/// Version of the config layout reported by `gcd __capabilities`.
+const CONFIG_VERSION: u32 = 1;
+
+/// An indexed repository along with metadata gathered when it was indexed.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "RepoEntryFormat")]
+struct RepoEntry {
+    #[serde(serialize_with = "serialize_os_path")]
+    path: PathBuf,
+    indexed_at: SystemTime,
+    /// When the repository was first added to the index. Refreshing or moving
+    /// an entry keeps it.
+    added_at: SystemTime,
+    default_branch: Option<String>,
+    /// The `origin` remote normalized to `host/org/name`, matched alongside the name
+    remote: Option<String>,
+    /// The scan path the repository was found under, if it was found by a scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_root: Option<PathBuf>,
+    /// The branch checked out when it was last indexed with `--branches`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_branch: Option<String>,
+}
+
+/// On-disk representations of a [`RepoEntry`]. Older configs stored a bare
+/// path per repository; those still load, with no metadata.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RepoEntryFormat {
+    Path(PathBuf),
+    Entry {
+        #[serde(deserialize_with = "deserialize_os_path")]
+        path: PathBuf,
+        #[serde(default = "unknown_time")]
+        indexed_at: SystemTime,
+        #[serde(default = "unknown_time")]
+        added_at: SystemTime,
+        #[serde(default)]
+        default_branch: Option<String>,
+        #[serde(default)]
+        remote: Option<String>,
+        #[serde(default)]
+        source_root: Option<PathBuf>,
+        #[serde(default)]
+        current_branch: Option<String>,
+    },
+}
+
+/// Parse a duration such as `12h`, `30d`, `6w` or `1y`.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let split = input.len() - input.chars().last().map_or(0, char::len_utf8);
+    let (amount, unit) = input.split_at(split);
+    let seconds = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(format!("expected a unit of h, d, w or y in '{}'", input)),
+    };
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(seconds))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}'", input))
+}
+
+/// A parsed `list --format` template.
+#[derive(Clone)]
+struct ListFormat(Vec<FormatPiece>);
+
+#[derive(Clone)]
+enum FormatPiece {
+    Text(String),
+    Name,
+    Path,
+    Parent,
+    Visits,
+    Branch,
+    Remote,
+    Tags,
+}
+
+/// Parse a `list --format` template. Besides the placeholders, `{{` and `}}`
+/// stand for literal braces and `\t`, `\n` and `\\` for a tab, a newline and
+/// a backslash, since shells don't expand those inside quotes.
+fn parse_list_format(input: &str) -> Result<ListFormat, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => text.push('\t'),
+                Some('n') => text.push('\n'),
+                Some('\\') => text.push('\\'),
+                Some(other) => {
+                    text.push('\\');
+                    text.push(other);
+                }
+                None => text.push('\\'),
+            },
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
             }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => {
+                            return Err(format!(
+                                "unterminated placeholder '{{{}' (use '{{{{' for a literal brace)",
+                                placeholder
+                            ))
+                        }
+                    }
+                }
+                let piece = match placeholder.as_str() {
+                    "name" => FormatPiece::Name,
+                    "path" => FormatPiece::Path,
+                    "parent" => FormatPiece::Parent,
+                    "visits" => FormatPiece::Visits,
+                    "branch" => FormatPiece::Branch,
+                    "remote" => FormatPiece::Remote,
+                    "tags" => FormatPiece::Tags,
+                    _ => return Err(format!("unknown placeholder '{{{}}}'", placeholder)),
+                };
+                if !text.is_empty() {
+                    pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(piece);
+            }
+            '}' => return Err("unmatched '}' (use '}}' for a literal brace)".to_string()),
+            _ => text.push(c),
         }
     }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    Ok(ListFormat(pieces))
+}
 
-    fn save(&self) -> std::io::Result<()> {
-        let config_path = config_path();
-        if let Some(parent) = config_path.parent() {
-            std::fs::create_dir_all(parent)?;
+impl ListFormat {
+    /// Render the template for one repository. Missing values (a repository
+    /// without a branch or remote) render as empty strings, and tags are
+    /// joined with commas.
+    fn render(&self, name: &str, entry: &RepoEntry, path: &Path, visits: u32, tags: &[String]) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                FormatPiece::Text(text) => out.push_str(text),
+                FormatPiece::Name => out.push_str(name),
+                FormatPiece::Path => out.push_str(&path.to_string_lossy()),
+                FormatPiece::Parent => {
+                    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        out.push_str(&parent.to_string_lossy());
+                    }
+                }
+                FormatPiece::Visits => out.push_str(&visits.to_string()),
+                FormatPiece::Branch => out.push_str(entry.default_branch.as_deref().unwrap_or("")),
+                FormatPiece::Remote => out.push_str(entry.remote.as_deref().unwrap_or("")),
+                FormatPiece::Tags => out.push_str(&tags.join(",")),
+            }
         }
-        let contents = serde_json::to_string_pretty(self)?;
-        std::fs::write(config_path, contents)
+        out
     }
 }
 
-fn config_path() -> PathBuf {
-    let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("~/.config"));
-    path.push("gcd");
-    path.push("config.json");
-    path
+/// Serialize a path as a string when it is valid UTF-8 and otherwise, on
+/// Unix, as an array of its raw bytes, so that a directory whose name isn't
+/// UTF-8 can still be stored in the config.
+fn serialize_os_path<S: serde::Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(path) => serializer.serialize_str(path),
+        #[cfg(unix)]
+        None => {
+            use std::os::unix::ffi::OsStrExt;
+            serializer.collect_seq(path.as_os_str().as_bytes())
+        }
+        #[cfg(not(unix))]
+        None => serializer.serialize_str(&path.to_string_lossy()),
+    }
 }
 
-fn find_git_repos(path: &Path) -> Vec<PathBuf> {
-    let mut repos = Vec::new();
-    for entry in WalkDir::new(path)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|e| {
-            e.file_name() != ".git"
-                && e.file_name() != "node_modules"
-                && e.file_name() != "target"
-        })
-    {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(_) => continue,
-        };
+/// Read a path written by [`serialize_os_path`].
+fn deserialize_os_path<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredPath {
+        Text(PathBuf),
+        Bytes(Vec<u8>),
+    }
+    Ok(match StoredPath::deserialize(deserializer)? {
+        StoredPath::Text(path) => path,
+        StoredPath::Bytes(bytes) => path_from_bytes(bytes),
+    })
+}
 
-        if entry.file_type().is_dir() && entry.path().join(".git").is_dir() {
-            repos.push(entry.path().to_path_buf());
-        }
+/// Build a path from raw bytes, such as a path printed by git. Outside Unix
+/// the bytes are assumed to be UTF-8.
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
     }
-    repos
 }
 
-fn install_shell_integration(shell: &str) -> std::io::Result<()> {
-    let script = if shell == "ps" {
-        // Handle PowerShell specifically
-        let profile_path = if let Ok(output) = std::process::Command::new("powershell")
-            .args(["-NoProfile", "-Command", "echo $PROFILE"])
-            .output()
-        {
-            let path_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !path_str.is_empty() {
-                PathBuf::from(path_str)
-            } else {
-                let docs = std::env::var("USERPROFILE")
-                    .map(PathBuf::from)
-                    .unwrap_or_else(|_| dirs::home_dir().expect("Could not find home directory"));
-                docs.join("Documents").join("WindowsPowerShell").join("Microsoft.PowerShell_profile.ps1")
-            }
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to get PowerShell profile path",
-            ));
-        };
+fn unknown_time() -> SystemTime {
+    SystemTime::UNIX_EPOCH
+}
+
+/// Serialize a map with its keys in order, so that saving an unchanged index
+/// writes an identical file rather than one shuffled by `HashMap`.
+fn serialize_sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: serde::Serializer,
+{
+    serializer.collect_map(map.iter().collect::<std::collections::BTreeMap<_, _>>())
+}
 
-        // Ensure the directory for the profile path exists
-        if let Some(parent) = profile_path.parent() {
-            std::fs::create_dir_all(parent)?;
+impl From<RepoEntryFormat> for RepoEntry {
+    fn from(format: RepoEntryFormat) -> Self {
+        match format {
+            RepoEntryFormat::Path(path) => RepoEntry {
+                path,
+                indexed_at: unknown_time(),
+                added_at: unknown_time(),
+                default_branch: None,
+                remote: None,
+                source_root: None,
+                current_branch: None,
+            },
+            RepoEntryFormat::Entry {
+                path,
+                indexed_at,
+                added_at,
+                default_branch,
+                remote,
+                source_root,
+                current_branch,
+            } => RepoEntry {
+                path,
+                indexed_at,
+                added_at,
+                default_branch,
+                remote,
+                source_root,
+                current_branch,
+            },
         }
+    }
+}
 
-        (profile_path, POWERSHELL_INTEGRATION)
-    } else {
-        let home_dir = dirs::home_dir().expect("Could not find home directory");
-        match shell {
-            "bash" => {
-                let script_path = home_dir.join(".bashrc");
-                (script_path, BASH_INTEGRATION)
-            }
-            "zsh" => {
-                let script_path = home_dir.join(".zshrc");
-                (script_path, ZSH_INTEGRATION)
-            }
-            "fish" => {
-                let mut script_path = home_dir;
-                script_path.push(".config");
-                script_path.push("fish");
-                script_path.push("config.fish");
-                (script_path, FISH_INTEGRATION)
-            }
-            _ => panic!("Unsupported shell"),
+impl RepoEntry {
+    /// Build an entry for the repository at `path`, querying git for metadata.
+    fn scan(path: PathBuf) -> Self {
+        let default_branch = default_branch(&path);
+        let remote = origin_url(&path).as_deref().and_then(normalize_remote);
+        let now = SystemTime::now();
+        RepoEntry {
+            path,
+            indexed_at: now,
+            added_at: now,
+            default_branch,
+            remote,
+            source_root: None,
+            current_branch: None,
         }
-    };
+    }
+}
 
-    let mut content = std::fs::read_to_string(&script.0).unwrap_or_default();
-    if !content.contains("### GCD Integration") {
-        content.push_str("\n### GCD Integration\n");
-        content.push_str(script.1);
-        std::fs::write(script.0, content)?;
+#[derive(Serialize, Deserialize)]
+struct Config {
+    #[serde(serialize_with = "serialize_sorted")]
+    repos: HashMap<String, RepoEntry>,
+    #[serde(default = "default_ignore_dirs")]
+    ignore_dirs: Vec<String>,
+    #[serde(default)]
+    scan_paths: Vec<PathBuf>,
+    #[serde(default)]
+    min_score: Option<i64>,
+    #[serde(default)]
+    resolution: Option<Resolution>,
+    #[serde(default)]
+    max_results: Option<u16>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    visits: HashMap<String, u32>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    last_visited: HashMap<String, SystemTime>,
+    /// Paths recently navigated to, most recent first, for `gcd -`
+    #[serde(default)]
+    history: Vec<PathBuf>,
+    #[serde(default)]
+    open_command: Option<String>,
+    /// Shell command run by the integration after entering a repository.
+    #[serde(default)]
+    hook: Option<String>,
+    /// Per-repository hooks, replacing `hook` for the named repositories.
+    #[serde(default, serialize_with = "serialize_sorted")]
+    hooks: HashMap<String, String>,
+    #[serde(default)]
+    prefer_recent: bool,
+    #[serde(default)]
+    auto_index: bool,
+    /// Record each repository's checked-out branch whenever indexing
+    #[serde(default)]
+    index_branches: bool,
+    #[serde(default)]
+    case_sensitive: bool,
+    #[serde(default)]
+    matcher: MatcherKind,
+    #[serde(default = "default_weight")]
+    name_weight: f64,
+    #[serde(default = "default_weight")]
+    alias_weight: f64,
+    #[serde(default = "default_weight")]
+    remote_weight: f64,
+    #[serde(default = "default_weight")]
+    path_weight: f64,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    aliases: HashMap<String, String>,
+    /// Tags of each repository, keyed by repository name
+    #[serde(default, serialize_with = "serialize_sorted")]
+    tags: HashMap<String, Vec<String>>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    scan_cache: ScanCache,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "serialize_sorted")]
+    profiles: HashMap<String, Profile>,
+    /// The profile selected with `--profile`, if not the default one. While
+    /// it is active, its index is swapped into the top-level fields and the
+    /// default profile's index is parked under `profiles`.
+    #[serde(skip)]
+    profile: Option<String>,
+    /// The config as it was loaded from disk, before any changes, so that
+    /// `save` can tell this process's changes from other processes' ones.
+    #[serde(skip)]
+    loaded: serde_json::Value,
+    /// Set by `--no-config-write`: saving is skipped, or refused when the
+    /// command can't do its job without it.
+    #[serde(skip)]
+    read_only: bool,
+    /// Matches remembered across runs, with `--cache-matches`
+    #[serde(skip)]
+    match_cache: Option<RefCell<MatchCache>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            repos: HashMap::new(),
+            ignore_dirs: default_ignore_dirs(),
+            scan_paths: Vec::new(),
+            min_score: None,
+            resolution: None,
+            max_results: None,
+            visits: HashMap::new(),
+            last_visited: HashMap::new(),
+            history: Vec::new(),
+            open_command: None,
+            hook: None,
+            hooks: HashMap::new(),
+            prefer_recent: false,
+            auto_index: false,
+            index_branches: false,
+            case_sensitive: false,
+            matcher: MatcherKind::Skim,
+            name_weight: default_weight(),
+            alias_weight: default_weight(),
+            remote_weight: default_weight(),
+            path_weight: default_weight(),
+            aliases: HashMap::new(),
+            tags: HashMap::new(),
+            scan_cache: ScanCache::new(),
+            profiles: HashMap::new(),
+            profile: None,
+            loaded: serde_json::Value::Null,
+            read_only: false,
+            match_cache: None,
+        }
     }
-    Ok(())
 }
 
+/// The index of a named profile. The default profile's index is stored at
+/// the top level of the config; settings are shared by all profiles.
+#[derive(Serialize, Deserialize, Default)]
+struct Profile {
+    #[serde(default, serialize_with = "serialize_sorted")]
+    repos: HashMap<String, RepoEntry>,
+    #[serde(default)]
+    scan_paths: Vec<PathBuf>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    visits: HashMap<String, u32>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    last_visited: HashMap<String, SystemTime>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    aliases: HashMap<String, String>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    hooks: HashMap<String, String>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    tags: HashMap<String, Vec<String>>,
+    #[serde(default, serialize_with = "serialize_sorted")]
+    scan_cache: ScanCache,
+}
 
+/// How many navigated paths are remembered for `gcd -`.
+const HISTORY_LEN: usize = 2;
 
-const BASH_INTEGRATION: &str = r#"
-gcd() {
-    if [ "$#" -eq 0 ]; then
-        command gcd
-    else
-        local output
-        output=$(command gcd "$@")
-        if [ $? -eq 0 ]; then
-            cd "$output" || return 1
-        else
-            echo "$output"
-            return 1
-        fi
-    fi
+/// Name of the profile kept at the top level of the config.
+const DEFAULT_PROFILE: &str = "default";
+
+/// Field weights above this are treated as this, keeping weighted scores
+/// far from overflowing.
+const MAX_WEIGHT: f64 = 100.0;
+
+fn default_weight() -> f64 {
+    1.0
 }
-"#;
 
-const ZSH_INTEGRATION: &str = BASH_INTEGRATION;
+fn default_ignore_dirs() -> Vec<String> {
+    vec![".git".into(), "node_modules".into(), "target".into()]
+}
 
-const FISH_INTEGRATION: &str = r#"
-function gcd
-    if test (count $argv) -eq 0
-        command gcd
-    else
-        set -l output (command gcd $argv)
-        if test $status -eq 0
-            cd $output
-        else
-            echo $output
-            return 1
-        end
-    end
-end
-"#;
+impl Config {
+    /// Load the config, or an empty one if none has been written yet. A
+    /// config that fails to parse is backed up and, unless `reset` is set,
+    /// aborts the program rather than being silently replaced.
+    fn load(reset: bool, profile: Option<&str>, read_only: bool) -> Result<Self, GcdError> {
+        // Not locked: saves rename a complete file into place, so a reader
+        // always sees either the old config or the new one
+        let config_path = config_path();
+        let contents = match std::fs::read_to_string(&config_path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(source) => {
+                return Err(GcdError::ConfigIo {
+                    action: "read config",
+                    path: config_path,
+                    source,
+                })
+            }
+        };
+        let format = ConfigFormat::of(&config_path);
+        let parsed = contents.map(|contents| format.parse(&contents));
+        let mut config = match parsed {
+            None => Config::default(),
+            Some(Ok(config)) => config,
+            Some(Err(e)) => Config::recover(&config_path, e, reset)?,
+        };
+        // Only needed for saving, which read-only configs never do
+        if !read_only {
+            config.loaded = serde_json::to_value(&config).unwrap_or_default();
+        }
+        if let Some(name) = profile.filter(|name| *name != DEFAULT_PROFILE) {
+            config.profile = Some(name.to_string());
+            config.swap_profile();
+        }
+        config.normalize_paths();
+        config.check_weights(&config_path)?;
+        config.read_only = read_only;
+        Ok(config)
+    }
 
-const POWERSHELL_INTEGRATION: &str = r#"
-function gcd {
-    if ($args.Count -eq 0) {
-        & gcd.exe
-    } else {
-        $output = & gcd.exe $args
-        if ($LASTEXITCODE -eq 0) {
-            Set-Location $output
-        } else {
-            Write-Host $output
-            return $LASTEXITCODE
+    /// Reject field weights that aren't finite numbers of at least zero, and
+    /// cap the rest at [`MAX_WEIGHT`].
+    fn check_weights(&mut self, config_path: &Path) -> Result<(), GcdError> {
+        let weights = [
+            ("name_weight", &mut self.name_weight),
+            ("alias_weight", &mut self.alias_weight),
+            ("remote_weight", &mut self.remote_weight),
+            ("path_weight", &mut self.path_weight),
+        ];
+        for (key, weight) in weights {
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(GcdError::Usage(format!(
+                    "`{}` in '{}' must be a number of at least 0, not {}",
+                    key,
+                    config_path.display(),
+                    weight
+                )));
+            }
+            *weight = weight.min(MAX_WEIGHT);
         }
+        Ok(())
     }
-}
-"#;
 
-fn main() {
-    let cli = Cli::parse();
-    let mut config = Config::load();
-
-    match cli.command {
-        Some(Commands::Index { path }) => {
-            let path = path.canonicalize().expect("Invalid path");
-            let repos = find_git_repos(&path);
-            for repo in repos {
-                let name = repo
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string();
-                config.repos.insert(name, repo);
-            }
-            config.save().expect("Failed to save config");
-            println!("Indexed repositories successfully");
-        }
-        Some(Commands::Install { shell }) => {
-            install_shell_integration(&shell).expect("Failed to install shell integration");
-            println!("Shell integration installed for {}", shell);
+    /// Remember `path` as the latest navigation, keeping the previous one for
+    /// `gcd -`.
+    fn record_history(&mut self, path: &Path) {
+        // The history is stored as text, so other paths can't be returned to
+        if path.to_str().is_none() {
+            return;
         }
-        None => {
-            if let Some(pattern) = cli.pattern {
-                let matcher = SkimMatcherV2::default();
-                let mut matches: Vec<_> = config
-                    .repos
-                    .iter()
-                    .filter_map(|(name, path)| {
-                        matcher
-                            .fuzzy_match(name, &pattern)
-                            .map(|score| (score, name, path))
-                    })
-                    .collect();
-
-                matches.sort_by(|a, b| b.0.cmp(&a.0));
-
-                if let Some((_, _, path)) = matches.first() {
-                    println!("{}", path.display());
-                } else {
-                    eprintln!("No matching repository found");
-                    std::process::exit(1);
-                }
-            } else {
-                println!("Available repositories:");
-                for (name, path) in config.repos {
-                    println!("{}: {}", name, path.display());
-                }
+        self.history.retain(|previous| previous != path);
+        self.history.insert(0, path.to_path_buf());
+        self.history.truncate(HISTORY_LEN);
+    }
+
+    /// The name of the active profile.
+    fn profile_name(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Exchange the index in the top-level fields with the one stored for
+    /// the active profile. Doing this twice restores the original layout.
+    fn swap_profile(&mut self) {
+        let Some(name) = self.profile.clone() else {
+            return;
+        };
+        let mut profile = self.profiles.remove(&name).unwrap_or_default();
+        std::mem::swap(&mut self.repos, &mut profile.repos);
+        std::mem::swap(&mut self.scan_paths, &mut profile.scan_paths);
+        std::mem::swap(&mut self.visits, &mut profile.visits);
+        std::mem::swap(&mut self.last_visited, &mut profile.last_visited);
+        std::mem::swap(&mut self.aliases, &mut profile.aliases);
+        std::mem::swap(&mut self.hooks, &mut profile.hooks);
+        std::mem::swap(&mut self.tags, &mut profile.tags);
+        std::mem::swap(&mut self.scan_cache, &mut profile.scan_cache);
+        self.profiles.insert(name, profile);
+    }
+
+    fn recover(config_path: &Path, error: String, reset: bool) -> Result<Self, GcdError> {
+        let mut backup = config_path.as_os_str().to_owned();
+        backup.push(".bak");
+        let backup = PathBuf::from(backup);
+        if let Err(source) = std::fs::copy(config_path, &backup) {
+            return Err(GcdError::ConfigIo {
+                action: "back up config to",
+                path: backup,
+                source,
+            });
+        }
+        eprintln!("The unreadable config was backed up to '{}'", backup.display());
+        if !reset {
+            return Err(GcdError::ConfigParse {
+                path: config_path.to_path_buf(),
+                error,
+            });
+        }
+        eprintln!("Warning: failed to parse config '{}': {}", config_path.display(), error);
+        eprintln!("Warning: continuing with an empty index");
+        Ok(Config::default())
+    }
+
+    /// Make hand-edited repository paths absolute, expanding a leading `~`
+    /// and resolving relative paths against the home directory. Entries that
+    /// can't be resolved are dropped with a warning.
+    fn normalize_paths(&mut self) {
+        let mut unresolved = Vec::new();
+        for (name, entry) in self.repos.iter_mut() {
+            if !needs_normalizing(&entry.path) {
+                continue;
+            }
+            match normalize_path(&entry.path) {
+                Some(path) => entry.path = path,
+                None => unresolved.push(name.clone()),
+            }
+        }
+        for name in unresolved {
+            if let Some(entry) = self.remove_repo(&name) {
+                eprintln!(
+                    "Warning: dropping '{}': cannot resolve path '{}'",
+                    name,
+                    entry.path.display()
+                );
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Write the config back to disk; a no-op when it is read-only. Under an
+    /// exclusive lock, the config is read again and only the changes made
+    /// since it was loaded are applied to it, so that concurrent `gcd`
+    /// processes (e.g. two terminals recording visits) keep each other's
+    /// changes to different settings and add up their visits. Where both
+    /// changed the same setting, the last save wins.
+    fn save(&mut self) -> std::io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let config_path = config_path();
+        // Put the active profile back in its place for writing
+        self.swap_profile();
+        let ours = serde_json::to_value(&*self);
+        self.swap_profile();
+        let ours = ours.map_err(std::io::Error::other)?;
+
+        let _lock = lock_config(&config_path)?;
+        let format = ConfigFormat::of(&config_path);
+        // A config that has gone missing or become unreadable is replaced
+        let theirs = std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|contents| format.parse(&contents).ok())
+            .and_then(|config| serde_json::to_value(&config).ok());
+        let merged = match theirs {
+            Some(theirs) => merge_changes(&self.loaded, ours.clone(), theirs, false),
+            None => ours.clone(),
+        };
+        let merged: Config = serde_json::from_value(merged).map_err(std::io::Error::other)?;
+        let contents = format.serialize(&merged)?;
+        // Write to a sibling file and rename it into place so that readers
+        // never observe a partially written config
+        let mut temp = config_path.clone().into_os_string();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        if let Err(e) = std::fs::write(&temp, contents) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        std::fs::rename(&temp, config_path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })?;
+        // Later saves only need to apply what changes after this one
+        self.loaded = ours;
+        Ok(())
+    }
+
+    /// Save changes the command can't do without, failing when the config is
+    /// read-only.
+    fn save_changes(&mut self) -> Result<(), GcdError> {
+        if self.read_only {
+            return Err(GcdError::ReadOnlyConfig);
+        }
+        self.save().map_err(|source| GcdError::ConfigIo {
+            action: "save config to",
+            path: config_path(),
+            source,
+        })
+    }
+
+    /// Whether the repository called `name` passes the `--tag` filter.
+    fn has_tag(&self, name: &str, tag: Option<&str>) -> bool {
+        tag.is_none_or(|tag| self.tags.get(name).is_some_and(|tags| tags.iter().any(|t| t == tag)))
+    }
+
+    /// Remove a repository along with its visit history and aliases.
+    fn remove_repo(&mut self, name: &str) -> Option<RepoEntry> {
+        self.visits.remove(name);
+        self.last_visited.remove(name);
+        self.aliases.retain(|_, target| target != name);
+        self.hooks.remove(name);
+        self.tags.remove(name);
+        self.repos.remove(name)
+    }
+
+    /// Fold the repository `from` into `into`, which stays as it is apart from
+    /// taking on the other's visits, aliases, tags and, if it has none of its
+    /// own, hook.
+    fn merge_repo(&mut self, from: &str, into: &str) {
+        let Some(entry) = self.repos.remove(from) else {
+            return;
+        };
+        if let Some(kept) = self.repos.get_mut(into) {
+            kept.added_at = kept.added_at.min(entry.added_at);
+        }
+        if let Some(visits) = self.visits.remove(from) {
+            *self.visits.entry(into.to_string()).or_insert(0) += visits;
+        }
+        if let Some(time) = self.last_visited.remove(from) {
+            let latest = self.last_visited.entry(into.to_string()).or_insert(time);
+            *latest = (*latest).max(time);
+        }
+        for target in self.aliases.values_mut().filter(|target| *target == from) {
+            *target = into.to_string();
+        }
+        if let Some(hook) = self.hooks.remove(from) {
+            self.hooks.entry(into.to_string()).or_insert(hook);
+        }
+        if let Some(tags) = self.tags.remove(from) {
+            let kept = self.tags.entry(into.to_string()).or_default();
+            kept.extend(tags);
+            kept.sort();
+            kept.dedup();
+        }
+    }
+
+    /// Move a repository, its visit history, aliases, hook and tags to a new key.
+    fn rename_repo(&mut self, old: &str, new: &str) {
+        if let Some(entry) = self.repos.remove(old) {
+            self.visits.remove(new);
+            if let Some(visits) = self.visits.remove(old) {
+                self.visits.insert(new.to_string(), visits);
+            }
+            self.last_visited.remove(new);
+            if let Some(time) = self.last_visited.remove(old) {
+                self.last_visited.insert(new.to_string(), time);
+            }
+            for target in self.aliases.values_mut().filter(|target| *target == old) {
+                *target = new.to_string();
+            }
+            self.hooks.remove(new);
+            if let Some(hook) = self.hooks.remove(old) {
+                self.hooks.insert(new.to_string(), hook);
+            }
+            self.tags.remove(new);
+            if let Some(tags) = self.tags.remove(old) {
+                self.tags.insert(new.to_string(), tags);
+            }
+            self.repos.insert(new.to_string(), entry);
+        }
+    }
+
+    /// Insert a repository, keeping any existing entry that points at a
+    /// different directory. If an entry with the same name points at a
+    /// directory that no longer exists, the repository is assumed to have
+    /// moved and that entry is updated in place. Otherwise clashing names are
+    /// disambiguated by appending the parent directory, e.g. `app (work)` next
+    /// to `app (personal)`. An entry already stored for the same path has its
+    /// metadata refreshed. Refreshed and moved entries keep their `added_at`,
+    /// and refreshed entries keep their `source_root` unless a scan found
+    /// them again.
+    fn insert_repo(&mut self, name: String, mut entry: RepoEntry) -> Insertion {
+        if let Some((key, existing)) = self
+            .repos
+            .iter_mut()
+            .find(|(_, existing)| existing.path == entry.path)
+        {
+            entry.added_at = existing.added_at;
+            if entry.source_root.is_none() {
+                entry.source_root = existing.source_root.take();
+            }
+            *existing = entry;
+            return Insertion::Existing(key.clone());
+        }
+        if !self.repos.contains_key(&name) {
+            self.repos.insert(name.clone(), entry);
+            return Insertion::Added(name);
+        }
+
+        let prefix = format!("{} (", name);
+        let mut moved: Vec<_> = self
+            .repos
+            .iter()
+            .filter(|(key, existing)| {
+                (**key == name || key.starts_with(&prefix)) && !existing.path.exists()
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        moved.sort();
+        if let Some(key) = moved.into_iter().next() {
+            entry.added_at = self.repos[&key].added_at;
+            self.repos.insert(key.clone(), entry);
+            return Insertion::Moved(key);
+        }
+
+        let parent = entry.path.parent().unwrap_or(&entry.path);
+        let parent_name = parent
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| parent.display().to_string());
+        let mut key = format!("{} ({})", name, parent_name);
+        if self.repos.contains_key(&key) {
+            key = format!("{} ({})", name, parent.display());
+        }
+        self.repos.insert(key.clone(), entry);
+        Insertion::Added(key)
+    }
+}
+
+/// Outcome of [`Config::insert_repo`], carrying the key the repository is
+/// stored under.
+enum Insertion {
+    Added(String),
+    Existing(String),
+    Moved(String),
+}
+
+impl Insertion {
+    fn into_key(self) -> String {
+        match self {
+            Insertion::Added(key) | Insertion::Existing(key) | Insertion::Moved(key) => key,
+        }
+    }
+}
+
+/// Whether `path` is anything other than a plain absolute path.
+fn needs_normalizing(path: &Path) -> bool {
+    !path.is_absolute()
+        || path.components().any(|c| {
+            matches!(c, std::path::Component::CurDir | std::path::Component::ParentDir)
+        })
+}
+
+/// Resolve `~`, relative (to the home directory) and `.`/`..` paths to an
+/// absolute path. Paths that exist are canonicalized.
+fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let expanded = match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir()?.join(rest),
+        Err(_) if path.is_relative() => dirs::home_dir()?.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    if let Ok(canonical) = expanded.canonicalize() {
+        return Some(canonical);
+    }
+
+    // Fall back to resolving `.` and `..` lexically for paths that don't exist
+    let mut normalized = PathBuf::new();
+    for component in expanded.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    Some(normalized)
+}
+
+/// Exit statuses `gcd` uses, so that scripts can tell failures apart.
+/// Argument errors caught by clap also exit with status 2.
+#[derive(Clone, Copy)]
+enum Exit {
+    /// No repository matched, or none was chosen
+    NoMatch = 1,
+    /// The command line asked for something that can't be done
+    Usage = 2,
+    /// Reading or writing a file, or running another program, failed
+    Io = 3,
+}
+
+impl Exit {
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+/// Everything that can stop a command, reported by `main` with the matching
+/// [`Exit`] status.
+#[derive(Debug)]
+enum GcdError {
+    /// No repository matched or none was chosen; the message (if any) is
+    /// printed as is
+    NoMatch(String),
+    /// The command line asked for something that can't be done
+    Usage(String),
+    UnsupportedShell {
+        shell: String,
+        supported: &'static [&'static str],
+    },
+    /// A change needs saving but `--no-config-write` is in effect
+    ReadOnlyConfig,
+    HomeDir,
+    /// Reading, writing or backing up the config failed
+    ConfigIo {
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The config exists but can't be parsed, and `--reset-config` wasn't given
+    ConfigParse { path: PathBuf, error: String },
+    /// A path given to `gcd index` can't be resolved
+    IndexPath { path: PathBuf, source: std::io::Error },
+    /// Any other file or process failure, described in full
+    Io(String),
+}
+
+impl GcdError {
+    fn status(&self) -> Exit {
+        match self {
+            GcdError::NoMatch(_) => Exit::NoMatch,
+            GcdError::Usage(_) | GcdError::UnsupportedShell { .. } | GcdError::ReadOnlyConfig => Exit::Usage,
+            GcdError::HomeDir
+            | GcdError::ConfigIo { .. }
+            | GcdError::ConfigParse { .. }
+            | GcdError::IndexPath { .. }
+            | GcdError::Io(_) => Exit::Io,
+        }
+    }
+}
+
+impl std::fmt::Display for GcdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GcdError::NoMatch(message) | GcdError::Usage(message) | GcdError::Io(message) => {
+                write!(f, "{}", message)
+            }
+            GcdError::UnsupportedShell { shell, supported } => {
+                write!(f, "unsupported shell '{}' (supported: {})", shell, supported.join(", "))
+            }
+            GcdError::ReadOnlyConfig => {
+                write!(f, "not saving changes: the config is read-only (--no-config-write)")
+            }
+            GcdError::HomeDir => write!(f, "could not find home directory"),
+            GcdError::ConfigIo { action, path, source } => {
+                write!(f, "failed to {} '{}': {}", action, path.display(), source)
+            }
+            GcdError::ConfigParse { path, error } => write!(
+                f,
+                "failed to parse config '{}': {}\nFix the file, or rerun with --reset-config to start with an empty index",
+                path.display(),
+                error
+            ),
+            GcdError::IndexPath { path, source } => {
+                write!(f, "cannot index '{}': {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GcdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GcdError::ConfigIo { source, .. } | GcdError::IndexPath { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Print an informational message to stdout unless `--quiet` was given.
+fn inform(quiet: bool, message: impl std::fmt::Display) {
+    if !quiet {
+        println!("{}", message);
+    }
+}
+
+/// The form of `path` to print: relative to the `--relative` base if one was
+/// given and a relative path leads there, e.g. not across Windows drives.
+fn shown_path<'a>(cli: &Cli, path: &'a Path) -> Cow<'a, Path> {
+    let Some(base) = &cli.relative else {
+        return Cow::Borrowed(path);
+    };
+    if path.components().next() != base.components().next() {
+        return Cow::Borrowed(path);
+    }
+    match pathdiff::diff_paths(path, base) {
+        Some(relative) if relative.as_os_str().is_empty() => Cow::Owned(PathBuf::from(".")),
+        Some(relative) => Cow::Owned(relative),
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Print a path to stdout byte for byte, so that the shell integration can
+/// enter directories whose names aren't valid UTF-8.
+fn emit_path(print0: bool, path: &Path) {
+    let mut stdout = std::io::stdout().lock();
+    let terminator: &[u8] = if print0 { b"\0" } else { b"\n" };
+    let _ = stdout
+        .write_all(path.as_os_str().as_encoded_bytes())
+        .and_then(|_| stdout.write_all(terminator));
+}
+
+/// First line of navigation output for shell integrations that set
+/// `GCD_HOOKS`; the directory to enter and any hook commands follow it.
+/// Output without it, from listings and other subcommands, is only echoed.
+const NAVIGATION_MARKER: &str = "\x1egcd-cd";
+
+/// Print the directory a navigation leads to. Hook-aware shell integrations
+/// get it after [`NAVIGATION_MARKER`], followed by `hook`; everyone else only
+/// ever sees the path.
+fn emit_destination(cli: &Cli, path: &Path, hook: Option<&str>) {
+    if cli.print0 || std::env::var_os("GCD_HOOKS").is_none() {
+        emit_path(cli.print0, &shown_path(cli, path));
+        return;
+    }
+    println!("{}", NAVIGATION_MARKER);
+    emit_path(false, &shown_path(cli, path));
+    if let Some(hook) = hook {
+        println!("{}", hook);
+    }
+}
+
+/// Print one record to stdout, terminated by a NUL byte instead of a newline
+/// when `print0` is set.
+fn emit(print0: bool, record: impl std::fmt::Display) {
+    if print0 {
+        print!("{}\0", record);
+    } else {
+        println!("{}", record);
+    }
+}
+
+/// Three-way merge of serialized configs: whatever this process changed
+/// since it loaded `base` is taken from `ours`, and everything else from
+/// `theirs`, the config as currently on disk. Objects are merged key by key,
+/// so visits to different repositories don't overwrite each other, and the
+/// visit counters of `counters` objects add up both sides' increments.
+fn merge_changes(
+    base: &serde_json::Value,
+    ours: serde_json::Value,
+    theirs: serde_json::Value,
+    counters: bool,
+) -> serde_json::Value {
+    use serde_json::Value;
+    if ours == *base {
+        return theirs;
+    }
+    match (base, ours, theirs) {
+        (Value::Object(base), Value::Object(ours), Value::Object(mut theirs)) => {
+            for key in base.keys().filter(|key| !ours.contains_key(*key)) {
+                theirs.remove(key);
+            }
+            for (key, ours) in ours {
+                let base = base.get(&key).unwrap_or(&Value::Null);
+                if ours == *base {
+                    continue;
+                }
+                // A repository may itself be called "visits"; only the visit
+                // maps hold nothing but numbers
+                let visits = key == "visits" && ours.as_object().is_some_and(|o| o.values().all(Value::is_u64));
+                let merged = match theirs.remove(&key) {
+                    Some(theirs) if counters => merge_counter(base, ours, theirs),
+                    Some(theirs) => merge_changes(base, ours, theirs, visits),
+                    None => ours,
+                };
+                theirs.insert(key, merged);
+            }
+            Value::Object(theirs)
+        }
+        (_, ours, _) => ours,
+    }
+}
+
+/// A counter both sides may have incremented: `theirs` plus whatever was
+/// added to `base` to get `ours`. A counter new to `ours` started from zero.
+fn merge_counter(base: &serde_json::Value, ours: serde_json::Value, theirs: serde_json::Value) -> serde_json::Value {
+    let count = |value: &serde_json::Value| value.as_u64().map(i128::from);
+    match (base.is_null().then_some(0).or(count(base)), count(&ours), count(&theirs)) {
+        (Some(base), Some(ours), Some(theirs)) => {
+            let merged = (theirs + ours - base).clamp(0, u32::MAX.into());
+            serde_json::Value::from(merged as u64)
+        }
+        _ => ours,
+    }
+}
+
+/// Take an exclusive advisory lock on a `.lock` file next to the config,
+/// blocking until any other `gcd` process has released it. Held only while
+/// a save reads and rewrites the config, never across a prompt.
+fn lock_config(config_path: &Path) -> std::io::Result<std::fs::File> {
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut lock_path = config_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+/// The config file in use: `GCD_CONFIG` if set, otherwise `config.json` in
+/// the user's config directory, or `config.toml` there when that is the
+/// only one present or `GCD_CONFIG_FORMAT=toml` asks for it.
+fn config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("GCD_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let mut dir = dirs::config_dir().unwrap_or_else(|| PathBuf::from("~/.config"));
+    dir.push("gcd");
+    let json = dir.join("config.json");
+    let toml = dir.join("config.toml");
+    match ConfigFormat::from_env().ok().flatten() {
+        Some(ConfigFormat::Toml) => toml,
+        Some(ConfigFormat::Json) => json,
+        None if toml.exists() && !json.exists() => toml,
+        None => json,
+    }
+}
+
+/// The file formats the config can be stored in.
+#[derive(Clone, Copy)]
+enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// The format requested with `GCD_CONFIG_FORMAT`, if any. The variable
+    /// is checked at startup, so later callers can ignore the error.
+    fn from_env() -> Result<Option<Self>, GcdError> {
+        let Some(value) = std::env::var("GCD_CONFIG_FORMAT").ok().filter(|value| !value.is_empty()) else {
+            return Ok(None);
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(Some(ConfigFormat::Json)),
+            "toml" => Ok(Some(ConfigFormat::Toml)),
+            _ => Err(GcdError::Usage(format!(
+                "unsupported GCD_CONFIG_FORMAT '{}' (expected json or toml)",
+                value
+            ))),
+        }
+    }
+
+    /// The format of the config at `path`: the one requested with
+    /// `GCD_CONFIG_FORMAT`, otherwise TOML for a `.toml` file and JSON for
+    /// anything else.
+    fn of(path: &Path) -> Self {
+        Self::from_env().ok().flatten().unwrap_or(match path.extension() {
+            Some(extension) if extension == "toml" => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        })
+    }
+
+    fn parse(self, contents: &str) -> Result<Config, String> {
+        match self {
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn serialize(self, config: &Config) -> std::io::Result<String> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(config)?),
+            ConfigFormat::Toml => toml::to_string_pretty(config).map_err(std::io::Error::other),
+        }
+    }
+}
+
+/// Options controlling how a directory tree is scanned for repositories and
+/// how the discovered repositories are indexed.
+struct ScanOptions {
+    depth: Option<usize>,
+    ignore_dirs: Vec<String>,
+    follow_links: bool,
+    threads: Option<usize>,
+    name_from_remote: bool,
+    name_segments: usize,
+    /// Re-read every repository even if it is unchanged since the last scan
+    full: bool,
+    /// Ask git to read each repository and skip the ones it can't
+    check: bool,
+    /// Leave repositories that are already indexed as they are
+    new_only: bool,
+    /// Skip hidden directories below the scan root
+    skip_hidden: bool,
+    /// Record the branch each repository has checked out
+    branches: bool,
+    /// Index the submodules git reports for each repository found
+    submodules: bool,
+    /// Skip repositories whose `origin` URL doesn't contain this
+    remote_filter: Option<String>,
+    verbose: bool,
+    quiet: bool,
+}
+
+impl ScanOptions {
+    fn from_config(config: &Config, cli: &Cli) -> Self {
+        ScanOptions {
+            depth: None,
+            ignore_dirs: config.ignore_dirs.clone(),
+            follow_links: true,
+            threads: None,
+            name_from_remote: false,
+            name_segments: 1,
+            full: false,
+            check: false,
+            new_only: false,
+            skip_hidden: false,
+            branches: config.index_branches,
+            submodules: false,
+            remote_filter: None,
+            verbose: cli.verbose,
+            quiet: cli.quiet,
+        }
+    }
+}
+
+/// Whether `path` is a repository working tree. `.git` is a directory for a
+/// regular clone and a file pointing elsewhere for worktrees and submodules.
+fn is_git_repo(path: &Path) -> bool {
+    let git = path.join(".git");
+    git.is_dir() || git.is_file()
+}
+
+/// Whether the repository at `path` is a submodule checked out inside
+/// another repository: its `.git` file points into the enclosing
+/// repository's `.git/modules`, whereas a worktree's points into
+/// `.git/worktrees`.
+fn is_submodule(path: &Path) -> bool {
+    let Ok(contents) = std::fs::read_to_string(path.join(".git")) else {
+        return false;
+    };
+    let Some(gitdir) = contents.lines().next().and_then(|line| line.strip_prefix("gitdir:")) else {
+        return false;
+    };
+    let components: Vec<_> = Path::new(gitdir.trim()).components().collect();
+    components
+        .windows(2)
+        .any(|pair| pair[0].as_os_str() == ".git" && pair[1].as_os_str() == "modules")
+}
+
+/// What makes a directory the same one however it was reached: its device and
+/// inode on Unix, its canonical path elsewhere.
+#[cfg(unix)]
+type DirIdentity = (u64, u64);
+#[cfg(not(unix))]
+type DirIdentity = PathBuf;
+
+/// The identity of the directory `path` resolves to, if it can be read.
+#[cfg(unix)]
+fn dir_identity(path: &Path) -> Option<DirIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_identity(path: &Path) -> Option<DirIdentity> {
+    path.canonicalize().ok()
+}
+
+/// Whether `path` is itself a git directory, as in a bare repository.
+fn is_bare_repo(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name != ".git")
+        && path.join("HEAD").is_file()
+        && path.join("objects").is_dir()
+        && path.join("refs").is_dir()
+}
+
+/// Name a repository after the last `segments` components of its path,
+/// joined by `/`, e.g. `teamA/frontend` for two segments.
+fn segmented_repo_name(path: &Path, segments: usize) -> String {
+    let mut parts = vec![repo_name(path)];
+    let mut ancestors = path.ancestors().skip(1);
+    while parts.len() < segments {
+        match ancestors.next().and_then(Path::file_name) {
+            Some(name) => parts.push(name.to_string_lossy().to_string()),
+            None => break,
+        }
+    }
+    parts.reverse();
+    parts.join("/")
+}
+
+/// Derive the index name for a repository from its directory, dropping the
+/// conventional `.git` suffix of bare repositories.
+fn repo_name(path: &Path) -> String {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return path.display().to_string(),
+    };
+    match name.strip_suffix(".git") {
+        Some(stripped) if !stripped.is_empty() && is_bare_repo(path) => stripped.to_string(),
+        _ => name,
+    }
+}
+
+/// How long an external command such as git may run before it is killed.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run `command` to completion with its output captured, killing it if it
+/// runs for longer than [`COMMAND_TIMEOUT`]. Running out of time is reported
+/// as a `TimedOut` error.
+fn run_with_timeout(command: &mut Command) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read both pipes while waiting, so a chatty child can't fill one up and
+    // block forever. They close when it exits, which is what is waited for.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let read = |pipe: Option<Box<dyn std::io::Read + Send>>, stderr: bool| {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut bytes);
+            }
+            let _ = sender.send((stderr, bytes));
+        });
+    };
+    read(child.stdout.take().map(|pipe| Box::new(pipe) as _), false);
+    read(child.stderr.take().map(|pipe| Box::new(pipe) as _), true);
+
+    let deadline = std::time::Instant::now() + COMMAND_TIMEOUT;
+    let timed_out = || {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("gave up after {}s", COMMAND_TIMEOUT.as_secs()),
+        )
+    };
+    let mut output = Output {
+        status: Default::default(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    for _ in 0..2 {
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        match receiver.recv_timeout(left) {
+            Ok((false, bytes)) => output.stdout = bytes,
+            Ok((true, bytes)) => output.stderr = bytes,
+            Err(_) => {
+                // The readers are left behind: anything the child started
+                // may still hold the pipes open
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(timed_out());
+            }
+        }
+    }
+    loop {
+        if let Some(status) = child.try_wait()? {
+            output.status = status;
+            return Ok(output);
+        }
+        if std::time::Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        std::thread::sleep(Duration::from_micros(100));
+    }
+}
+
+/// Run `command` like [`run_with_timeout`] and return what it printed to
+/// stdout. Failing to start it, a timeout or a non-zero exit are errors that
+/// name the program and, for the latter, the first line of its stderr.
+fn command_stdout(command: &mut Command) -> std::io::Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = run_with_timeout(command)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = match stderr.lines().next().map(str::trim).filter(|line| !line.is_empty()) {
+            Some(line) => format!("{} exited with {}: {}", program, output.status, line),
+            None => format!("{} exited with {}", program, output.status),
+        };
+        return Err(std::io::Error::other(message));
+    }
+    Ok(output.stdout)
+}
+
+/// Ask git about the repository at `path` (or the current directory),
+/// returning its stdout. A non-zero exit just means there's no answer, e.g.
+/// no `origin` remote; git being missing or hanging is worth a warning, but
+/// only once, since metadata is queried for every repository indexed.
+fn git_query(path: Option<&Path>, args: &[&str]) -> Option<Vec<u8>> {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    let mut command = Command::new("git");
+    if let Some(path) = path {
+        command.arg("-C").arg(path);
+    }
+    match run_with_timeout(command.args(args)) {
+        Ok(output) => output.status.success().then_some(output.stdout),
+        Err(e) => {
+            WARNED.call_once(|| eprintln!("Warning: failed to run git: {}", e));
+            None
+        }
+    }
+}
+
+/// The URL of a repository's `origin` remote, with any `insteadOf`
+/// rewrites from the git config applied.
+fn origin_url(path: &Path) -> Option<String> {
+    let stdout = git_query(Some(path), &["remote", "get-url", "origin"])?;
+    let url = String::from_utf8_lossy(&stdout).trim().to_string();
+    (!url.is_empty()).then_some(url)
+}
+
+/// Normalize a remote URL to `host/org/name`, so that
+/// `https://github.com/me/app.git`, `ssh://git@github.com:22/me/app` and
+/// `git@github.com:me/app.git` all become `github.com/me/app`. Local paths
+/// and `file://` URLs have no host and yield `None`.
+fn normalize_remote(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    let (host, path) = match url.split_once("://") {
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit('@').next()?;
+            let host = match host.rsplit_once(':') {
+                Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+                _ => host,
+            };
+            (host, path)
+        }
+        // scp-like syntax, `user@host:path`. A `/` before the `:`, or a lone
+        // drive letter, means a local path
+        None => {
+            let (authority, path) = url.split_once(':')?;
+            if authority.len() == 1 || authority.contains(['/', '\\']) {
+                return None;
+            }
+            (authority.rsplit('@').next()?, path)
+        }
+    };
+    let path = path.trim_start_matches('/');
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some(format!("{}/{}", host.to_lowercase(), path))
+}
+
+/// Derive a repository name from its `origin` remote URL, e.g.
+/// `git@github.com:me/app.git` becomes `app`.
+fn remote_repo_name(path: &Path) -> Option<String> {
+    let url = origin_url(path)?;
+    let last = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':', '\\'])
+        .next()?;
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Top-level directory of the git repository containing the current directory.
+fn git_toplevel() -> Option<PathBuf> {
+    let mut root = git_query(None, &["rev-parse", "--show-toplevel"])?;
+    while root.last().is_some_and(u8::is_ascii_whitespace) {
+        root.pop();
+    }
+    (!root.is_empty()).then(|| path_from_bytes(root))
+}
+
+/// The branch checked out in a repository, or `None` on a detached `HEAD`.
+/// Unlike `rev-parse --abbrev-ref`, `symbolic-ref` also names a branch
+/// without any commits yet.
+fn current_branch(path: &Path) -> Option<String> {
+    let stdout = git_query(Some(path), &["symbolic-ref", "--quiet", "--short", "HEAD"])?;
+    let branch = String::from_utf8_lossy(&stdout).trim().to_string();
+    (!branch.is_empty()).then_some(branch)
+}
+
+/// Determine the default branch of a repository: whatever `origin/HEAD`
+/// points at, otherwise a local `main` or `master` branch.
+fn default_branch(path: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        git_query(Some(path), args).map(|stdout| String::from_utf8_lossy(&stdout).trim().to_string())
+    };
+
+    if let Some(head) = git(&["symbolic-ref", "--quiet", "--short", "refs/remotes/origin/HEAD"]) {
+        return Some(head.strip_prefix("origin/").unwrap_or(&head).to_string());
+    }
+    ["main", "master"]
+        .into_iter()
+        .find(|branch| {
+            git(&["rev-parse", "--verify", "--quiet", &format!("refs/heads/{}", branch)]).is_some()
+        })
+        .map(str::to_string)
+}
+
+/// Load glob patterns from a `.gcdignore` file in the scan root, one per line.
+/// Blank lines and lines starting with `#` are skipped.
+fn load_gcdignore(root: &Path) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    let contents = std::fs::read_to_string(root.join(".gcdignore")).unwrap_or_default();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match Glob::new(line.trim_end_matches('/')) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => eprintln!("Warning: ignoring invalid .gcdignore pattern '{}': {}", line, e),
+        }
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// Modification times of repository directories as of the last scan.
+type ScanCache = HashMap<PathBuf, SystemTime>;
+
+/// Repositories found by [`find_git_repos`].
+#[derive(Default)]
+struct Discovery {
+    /// Repositories that are new or changed since the last scan
+    changed: Vec<PathBuf>,
+    /// Repositories whose directory is unchanged since the last scan
+    unchanged: Vec<PathBuf>,
+    /// Up-to-date modification times of the repositories found
+    mtimes: ScanCache,
+    /// Number of directories looked at
+    visited: usize,
+}
+
+/// Walk `path` looking for git repositories, reading directories in parallel.
+/// Directories named in `options.ignore_dirs` are always skipped; on top of
+/// that, any directory whose path relative to `path` (or whose bare name)
+/// matches a `.gcdignore` pattern in the scan root is skipped too.
+///
+/// A repository whose modification time (see [`repo_mtime`]) matches
+/// `previous` is reported as unchanged, so its metadata needn't be read
+/// again; unless `options.full` is set. It is still walked into, since a
+/// directory's modification time only reflects its direct children and would
+/// miss a repository added deeper inside.
+///
+/// When following symbolic links, a directory that was already reached by
+/// another route is skipped, so links pointing back up the tree can't make
+/// the walk go round in circles.
+fn find_git_repos(
+    path: &Path,
+    options: &ScanOptions,
+    previous: &ScanCache,
+) -> Discovery {
+    let root = path.to_path_buf();
+    let gcdignore = load_gcdignore(path);
+    let ignore_dirs = options.ignore_dirs.clone();
+    let skip_hidden = options.skip_hidden;
+    let include_submodules = options.submodules;
+    // The root is among the first batch of children, so it gets recorded too
+    let seen: Option<Arc<Mutex<HashSet<DirIdentity>>>> =
+        options.follow_links.then(|| Arc::new(Mutex::new(HashSet::new())));
+    let discovery = Arc::new(Mutex::new(Discovery::default()));
+    let previous: Arc<ScanCache> = Arc::new(if options.full {
+        ScanCache::new()
+    } else {
+        previous
+            .iter()
+            .filter(|(repo, _)| repo.starts_with(path))
+            .map(|(repo, mtime)| (repo.clone(), *mtime))
+            .collect()
+    });
+
+    let threads = options.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let parallelism = if threads > 1 {
+        Parallelism::RayonNewPool(threads)
+    } else {
+        Parallelism::Serial
+    };
+
+    let found = Arc::clone(&discovery);
+    let verbose = options.verbose;
+    let mut walker = WalkDir::new(path)
+        .follow_links(options.follow_links)
+        .skip_hidden(false)
+        .parallelism(parallelism)
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|entry| {
+                let Ok(e) = entry else {
+                    return false;
+                };
+                if ignore_dirs.iter().any(|dir| e.file_name() == dir.as_str()) {
+                    return false;
+                }
+                // Repositories are recognized by looking for `.git` inside them,
+                // not by walking into it, so pruning hidden entries loses nothing
+                // but repositories whose own name starts with a dot
+                if skip_hidden && e.depth() > 0 && e.file_name().as_encoded_bytes().starts_with(b".") {
+                    return false;
+                }
+                if let Some(seen) = seen.as_ref().filter(|_| e.file_type().is_dir()) {
+                    if let Some(identity) = dir_identity(&e.path()) {
+                        if !seen.lock().unwrap().insert(identity) {
+                            return false;
+                        }
+                    }
+                }
+                let entry_path = e.path();
+                match entry_path.strip_prefix(&root) {
+                    Ok(relative) if e.depth() > 0 => {
+                        !(gcdignore.is_match(relative) || gcdignore.is_match(e.file_name()))
+                    }
+                    _ => true,
+                }
+            });
+
+            for entry in children.iter_mut().flatten() {
+                if !entry.file_type().is_dir() {
+                    continue;
+                }
+                found.lock().unwrap().visited += 1;
+                let entry_path = entry.path();
+                let bare = is_bare_repo(&entry_path);
+                if bare {
+                    // Nothing worth finding inside a bare repository's internals
+                    entry.read_children_path = None;
+                }
+                if !bare && !is_git_repo(&entry_path) {
+                    continue;
+                }
+                // Still walked into, for any repository nested inside
+                if !include_submodules && is_submodule(&entry_path) {
+                    continue;
+                }
+
+                let mtime = repo_mtime(&entry_path);
+                let unchanged = mtime.is_some() && previous.get(&entry_path) == mtime.as_ref();
+                let mut found = found.lock().unwrap();
+                if verbose {
+                    let count = found.changed.len() + found.unchanged.len() + 1;
+                    let note = if unchanged { " (unchanged)" } else { "" };
+                    eprintln!("[{}] {}{}", count, entry_path.display(), note);
+                }
+                if let Some(mtime) = mtime {
+                    found.mtimes.insert(entry_path.clone(), mtime);
+                }
+                if unchanged {
+                    found.unchanged.push(entry_path);
+                } else {
+                    found.changed.push(entry_path);
+                }
+            }
+        });
+    if let Some(depth) = options.depth {
+        walker = walker.max_depth(depth);
+    }
+    walker.into_iter().for_each(drop);
+
+    let mut discovery = std::mem::take(&mut *discovery.lock().unwrap());
+    // Parallel discovery finishes in arbitrary order; sort so that naming of
+    // duplicate repositories stays stable between runs
+    discovery.changed.sort();
+    discovery.unchanged.sort();
+    discovery.unchanged.dedup();
+    discovery
+}
+
+/// The latest modification time of a repository's directory and of the git
+/// files its indexed metadata comes from, so that e.g. `git remote add` or
+/// switching branches marks it as changed even though only `.git/config` or
+/// `.git/HEAD` was written.
+fn repo_mtime(path: &Path) -> Option<SystemTime> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mtime = modified(path)?;
+    let git_dir = path.join(".git");
+    // Bare repositories keep `HEAD` and `config` at the top level
+    let files = [git_dir.clone(), git_dir.join("HEAD"), git_dir.join("config"), path.join("HEAD"), path.join("config")];
+    files.iter().filter_map(|file| modified(file)).max().max(Some(mtime))
+}
+
+/// Check that git can read the repository at `path`, reporting it on stderr
+/// if not.
+fn check_repo(path: &Path) -> bool {
+    let output = run_with_timeout(
+        Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["rev-parse", "--is-inside-work-tree"]),
+    );
+    let problem = match output {
+        Ok(output) if output.status.success() => return true,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            stderr.lines().next().unwrap_or("git failed to read it").trim().to_string()
+        }
+        Err(e) => format!("failed to run git: {}", e),
+    };
+    eprintln!("Skipping broken repository '{}': {}", path.display(), problem);
+    false
+}
+
+/// Whether a discovered repository passes the `--check` and
+/// `--remote-filter` options.
+fn accept_repo(path: &Path, options: &ScanOptions) -> bool {
+    if options.check && !check_repo(path) {
+        return false;
+    }
+    if let Some(filter) = &options.remote_filter {
+        if !origin_url(path).is_some_and(|url| url.contains(filter.as_str())) {
+            if options.verbose {
+                eprintln!("Skipping '{}': origin doesn't match '{}'", path.display(), filter);
+            }
+            return false;
+        }
+    }
+    true
+}
+
+/// Scan `paths` and index every repository found. Returns the names the
+/// repositories were stored under.
+fn index_paths(
+    config: &mut Config,
+    paths: &[PathBuf],
+    options: &ScanOptions,
+) -> Result<Vec<String>, GcdError> {
+    let mut keys = Vec::new();
+    for path in paths {
+        let path = path.canonicalize().map_err(|source| GcdError::IndexPath {
+            path: path.clone(),
+            source,
+        })?;
+        let mut discovery = find_git_repos(&path, options, &config.scan_cache);
+        if options.submodules {
+            let found: HashSet<_> = discovery.changed.iter().chain(&discovery.unchanged).cloned().collect();
+            let mut nested: Vec<_> = found
+                .iter()
+                .flat_map(|repo| submodules(repo))
+                .filter(|submodule| !found.contains(submodule))
+                .collect();
+            nested.sort();
+            nested.dedup();
+            discovery.changed.extend(nested);
+        }
+        let found = !discovery.changed.is_empty() || !discovery.unchanged.is_empty();
+        if !found {
+            eprintln!("No git repositories found under '{}'", path.display());
+        }
+        // Like `scan_paths`, only roots that can be written as text are recorded
+        let root = path.to_str().is_some().then(|| path.clone());
+
+        // Unchanged repositories keep their entry as is, which saves querying
+        // git for their metadata again
+        for repo in discovery.unchanged {
+            if !accept_repo(&repo, options) {
+                continue;
+            }
+            let existing = config.repos.iter_mut().find(|(_, entry)| entry.path == repo);
+            match existing {
+                // Leave repositories already indexed exactly as they are
+                Some((key, _)) if options.new_only => keys.push(key.clone()),
+                Some((key, entry)) => {
+                    // Entries indexed before roots were recorded pick one up
+                    if entry.source_root.is_none() {
+                        entry.source_root = root.clone();
+                    }
+                    // A worktree's `HEAD` lives in the main repository, so an
+                    // unchanged one may still be on a different branch
+                    if options.branches {
+                        entry.current_branch = current_branch(&repo);
+                    }
+                    keys.push(key.clone())
+                }
+                None => keys.push(index_repo(config, repo, root.clone(), options)),
+            }
+        }
+        for repo in discovery.changed {
+            if !accept_repo(&repo, options) {
+                continue;
+            }
+            keys.push(index_repo(config, repo, root.clone(), options));
+        }
+        config.scan_cache.retain(|repo, _| !repo.starts_with(&path));
+        // Map keys must be strings, so non-UTF-8 paths are simply never cached
+        let mtimes = discovery.mtimes.into_iter().filter(|(repo, _)| repo.to_str().is_some());
+        config.scan_cache.extend(mtimes);
+        if path.to_str().is_none() {
+            eprintln!(
+                "Warning: '{}' is not valid UTF-8 and won't be rescanned by `gcd reindex`",
+                path.display()
+            );
+        } else if found && !config.scan_paths.contains(&path) {
+            // A mistyped path shouldn't be rescanned by every `reindex`
+            config.scan_paths.push(path);
+        }
+    }
+    Ok(keys)
+}
+
+/// The initialized submodules of the repository at `path`, nested ones
+/// included, as listed by `git submodule status --recursive`.
+fn submodules(path: &Path) -> Vec<PathBuf> {
+    let Some(stdout) = git_query(Some(path), &["submodule", "status", "--recursive"]) else {
+        return Vec::new();
+    };
+    // Each line is a status character, the commit, the path and, for
+    // checked-out submodules, a parenthesized description
+    String::from_utf8_lossy(&stdout)
+        .lines()
+        .filter(|line| !line.starts_with('-'))
+        .filter_map(|line| {
+            let (_, rest) = line.get(1..)?.split_once(' ')?;
+            let relative = match rest.rfind(" (") {
+                Some(at) if rest.ends_with(')') => &rest[..at],
+                _ => rest,
+            };
+            Some(path.join(relative))
+        })
+        .filter(|submodule| is_git_repo(submodule))
+        .collect()
+}
+
+/// Add a single discovered repository to the index, recording the scan path
+/// it was found under when there is one.
+fn index_repo(
+    config: &mut Config,
+    repo: PathBuf,
+    source_root: Option<PathBuf>,
+    options: &ScanOptions,
+) -> String {
+    if options.new_only {
+        if let Some((key, _)) = config.repos.iter().find(|(_, entry)| entry.path == repo) {
+            if options.verbose {
+                eprintln!("Skipping '{}': already indexed", key);
+            }
+            return key.clone();
+        }
+    }
+    let name = options
+        .name_from_remote
+        .then(|| remote_repo_name(&repo))
+        .flatten()
+        .unwrap_or_else(|| segmented_repo_name(&repo, options.name_segments));
+    if repo.to_str().is_none() {
+        eprintln!(
+            "Warning: '{}' is not valid UTF-8; it is indexed as '{}'",
+            repo.display(),
+            name
+        );
+    }
+    let current_branch = options.branches.then(|| current_branch(&repo)).flatten();
+    let entry = RepoEntry {
+        source_root,
+        current_branch,
+        ..RepoEntry::scan(repo)
+    };
+    let insertion = config.insert_repo(name, entry);
+    if let Insertion::Moved(key) = &insertion {
+        inform(options.quiet, format!("Updated path for '{}'", key));
+    }
+    insertion.into_key()
+}
+
+/// A `--rewrite` of exported paths: `from` is replaced by `to` in paths
+/// starting with it.
+#[derive(Clone)]
+struct PathRewrite {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// Parse `FROM=TO` for `gcd import --rewrite`.
+fn parse_path_rewrite(input: &str) -> Result<PathRewrite, String> {
+    match input.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(PathRewrite {
+            from: PathBuf::from(from),
+            to: PathBuf::from(to),
+        }),
+        _ => Err(format!("expected FROM=TO, e.g. /home/me=/Users/me, not '{}'", input)),
+    }
+}
+
+/// Apply the rewrite with the longest `from` that `path` starts with, if
+/// any. Prefixes match whole path components, so `/home/me` leaves
+/// `/home/meg` alone.
+fn rewrite_path(path: PathBuf, rewrites: &[PathRewrite]) -> PathBuf {
+    let best = rewrites
+        .iter()
+        .filter_map(|rewrite| Some((rewrite, path.strip_prefix(&rewrite.from).ok()?)))
+        .max_by_key(|(rewrite, _)| rewrite.from.components().count());
+    match best {
+        Some((rewrite, rest)) if rest.as_os_str().is_empty() => rewrite.to.clone(),
+        Some((rewrite, rest)) => rewrite.to.join(rest),
+        None => path,
+    }
+}
+
+/// Merge exported repositories into the index, with their paths rewritten
+/// by `rewrites`. Entries whose path doesn't exist on this machine are kept,
+/// with a warning, so they can be fixed up by hand or cloned later; entries
+/// without a path can only be reported. Returns the number of repositories
+/// imported.
+fn import_exported(config: &mut Config, repos: Vec<ExportedRepo>, rewrites: &[PathRewrite]) -> usize {
+    let mut count = 0;
+    for repo in repos {
+        let Some(path) = repo.path.map(|path| rewrite_path(path, rewrites)) else {
+            if !config.repos.contains_key(&repo.name) {
+                eprintln!("Skipping '{}': no path was exported", repo.name);
+            }
+            continue;
+        };
+        let entry = if path.exists() {
+            RepoEntry::scan(path)
+        } else {
+            RepoEntry {
+                path,
+                indexed_at: unknown_time(),
+                added_at: SystemTime::now(),
+                default_branch: None,
+                remote: repo.remote,
+                source_root: None,
+                current_branch: None,
+            }
+        };
+        let missing = (!entry.path.exists()).then(|| entry.path.clone());
+        let key = config.insert_repo(repo.name, entry).into_key();
+        if let Some(path) = missing {
+            eprintln!("Warning: '{}' does not exist on this machine: {}", key, path.display());
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Index repositories from a list of paths, one per line, without walking
+/// the filesystem. Lines that aren't git repositories are reported and
+/// skipped. Returns the names the repositories were stored under.
+fn import_repo_paths(
+    config: &mut Config,
+    lines: impl Iterator<Item = String>,
+    options: &ScanOptions,
+) -> Vec<String> {
+    let mut keys = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let path = match Path::new(line).canonicalize() {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Skipping '{}': {}", line, e);
+                continue;
+            }
+        };
+        if !is_git_repo(&path) && !is_bare_repo(&path) {
+            eprintln!("Skipping '{}': not a git repository", line);
+            continue;
+        }
+        if !accept_repo(&path, options) {
+            continue;
+        }
+        let mut nested = if options.submodules { submodules(&path) } else { Vec::new() };
+        nested.retain(|repo| accept_repo(repo, options));
+        for repo in std::iter::once(path).chain(nested) {
+            if options.verbose {
+                eprintln!("[{}] {}", keys.len() + 1, repo.display());
+            }
+            keys.push(index_repo(config, repo, None, options));
+        }
+    }
+    keys
+}
+
+/// Settings that shape how a pattern is matched against the index.
+struct MatchOptions {
+    /// Also match against the full path, not just the name
+    match_path: bool,
+    /// Drop candidates scoring below this
+    min_score: Option<i64>,
+    /// Require the case of the pattern to match exactly
+    case_sensitive: bool,
+    /// Which fuzzy matching algorithm scores candidates
+    matcher: MatcherKind,
+    /// Multipliers applied to the score of each searchable field
+    weights: FieldWeights,
+    /// Aliases of each repository, keyed by repository name
+    aliases: HashMap<String, Vec<String>>,
+}
+
+struct FieldWeights {
+    name: f64,
+    alias: f64,
+    remote: f64,
+    path: f64,
+}
+
+impl MatchOptions {
+    fn new(cli: &Cli, config: &Config) -> Self {
+        MatchOptions {
+            match_path: cli.path,
+            min_score: cli.min_score.or(config.min_score),
+            case_sensitive: cli.case_sensitive || config.case_sensitive,
+            matcher: cli.matcher.unwrap_or(config.matcher),
+            weights: FieldWeights {
+                name: config.name_weight,
+                alias: config.alias_weight,
+                remote: config.remote_weight,
+                path: config.path_weight,
+            },
+            aliases: config.aliases.iter().fold(HashMap::new(), |mut aliases, (alias, target)| {
+                aliases.entry(target.clone()).or_default().push(alias.clone());
+                aliases
+            }),
+        }
+    }
+
+    fn matcher(&self) -> Box<dyn FuzzyMatcher> {
+        match (self.matcher, self.case_sensitive) {
+            (MatcherKind::Skim, true) => Box::new(SkimMatcherV2::default().respect_case()),
+            (MatcherKind::Skim, false) => Box::new(SkimMatcherV2::default().ignore_case()),
+            (MatcherKind::Clangd, true) => Box::new(ClangdMatcher::default().respect_case()),
+            (MatcherKind::Clangd, false) => Box::new(ClangdMatcher::default().ignore_case()),
+        }
+    }
+}
+
+/// Score added per pattern character when the pattern spells the start of a
+/// name's initialism, so that `mpp` clearly prefers `my-python-project` over
+/// names that merely contain those letters.
+const INITIALISM_BONUS: i64 = 30;
+
+/// The first letter of each word in `name`, splitting on `-`, `_`, `.`,
+/// spaces and lower-to-upper case changes: `my-python-project` and
+/// `myPythonProject` both give `mpp`.
+fn initialism(name: &str) -> String {
+    let mut initials = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        let starts_word = match previous {
+            None => true,
+            Some(p) => matches!(p, '-' | '_' | '.' | ' ') || (p.is_lowercase() && c.is_uppercase()),
+        };
+        if starts_word && c.is_alphanumeric() {
+            initials.push(c);
+        }
+        previous = Some(c);
+    }
+    initials
+}
+
+/// The initialism bonus for `field`, if `pattern` (at least two characters)
+/// is a prefix of its initials.
+fn initialism_bonus(field: &str, pattern: &str, case_sensitive: bool) -> Option<i64> {
+    let length = pattern.chars().count();
+    if length < 2 {
+        return None;
+    }
+    let initials = initialism(field);
+    let matches = if case_sensitive {
+        initials.starts_with(pattern)
+    } else {
+        initials.to_lowercase().starts_with(&pattern.to_lowercase())
+    };
+    matches.then_some(INITIALISM_BONUS * length as i64)
+}
+
+/// Fuzzy-match `pattern` against each searchable field of a repository (its
+/// name, aliases, normalized remote and, for path patterns, its path), scale
+/// each score by the field's weight and keep the best. A field weighted zero
+/// or less is ignored. Names and aliases whose initials start with the
+/// pattern get [`INITIALISM_BONUS`] on top of their fuzzy score.
+fn score_repo(
+    matcher: &dyn FuzzyMatcher,
+    name: &str,
+    entry: &RepoEntry,
+    pattern: &str,
+    options: &MatchOptions,
+) -> Option<i64> {
+    let weigh = |field: &str, weight: f64| {
+        if weight <= 0.0 {
+            return None;
+        }
+        let score = matcher.fuzzy_match(field, pattern)?;
+        Some((score as f64 * weight).round() as i64)
+    };
+    let abbreviated = |field: &str, weight: f64| {
+        let score = weigh(field, weight);
+        if weight <= 0.0 {
+            return score;
+        }
+        match initialism_bonus(field, pattern, options.case_sensitive) {
+            Some(bonus) => Some(score.unwrap_or(0) + (bonus as f64 * weight).round() as i64),
+            None => score,
+        }
+    };
+    let weights = &options.weights;
+
+    let mut best = abbreviated(name, weights.name);
+    for alias in options.aliases.get(name).into_iter().flatten() {
+        best = best.max(abbreviated(alias, weights.alias));
+    }
+    if let Some(remote) = &entry.remote {
+        best = best.max(weigh(remote, weights.remote));
+    }
+    if options.match_path || pattern.contains('/') {
+        best = best.max(weigh(&entry.path.display().to_string(), weights.path));
+    }
+    best
+}
+
+/// Fuzzy match `pattern` against the indexed repositories, returning every
+/// candidate in no particular order. Patterns containing a `/` are matched
+/// against full paths as well as names.
+fn scored_matches<'a>(
+    repos: &'a HashMap<String, RepoEntry>,
+    pattern: &str,
+    options: &MatchOptions,
+) -> Vec<(i64, &'a String, &'a PathBuf)> {
+    let matcher = options.matcher();
+    repos
+        .iter()
+        .filter_map(|(name, entry)| {
+            score_repo(matcher.as_ref(), name, entry, pattern, options)
+                .map(|score| (score, name, &entry.path))
+        })
+        .filter(|(score, _, _)| options.min_score.is_none_or(|min| *score >= min))
+        .collect()
+}
+
+/// Like [`scored_matches`], sorted by descending score. Ties are ordered by
+/// name so the result does not depend on `HashMap` iteration order.
+fn best_matches<'a>(
+    repos: &'a HashMap<String, RepoEntry>,
+    pattern: &str,
+    options: &MatchOptions,
+) -> Vec<(i64, &'a String, &'a PathBuf)> {
+    let mut matches = scored_matches(repos, pattern, options);
+    // `repos` iterates in random order, so fall back to the name and path to
+    // keep equal scores in the same order from run to run
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)).then_with(|| a.2.cmp(b.2)));
+    matches
+}
+
+/// Repositories whose name satisfies `is_match`, in alphabetical order. Used
+/// for the regex and glob modes, which have no notion of a score.
+fn filter_matches(
+    repos: &HashMap<String, RepoEntry>,
+    is_match: impl Fn(&str) -> bool,
+) -> Vec<(i64, &String, &PathBuf)> {
+    let mut matches: Vec<_> = repos
+        .iter()
+        .filter(|(name, _)| is_match(name))
+        .map(|(name, entry)| (0, name, &entry.path))
+        .collect();
+    matches.sort_by(|a, b| a.1.cmp(b.1));
+    matches
+}
+
+/// Open `path` with the configured `open_command`, or the platform's default
+/// opener when none is set. The command may include extra arguments, e.g.
+/// `"code -n"`.
+fn open_repo(command: Option<&str>, path: &Path) -> std::io::Result<()> {
+    let default = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut parts = command.unwrap_or(default).split_whitespace();
+    let program = parts.next().unwrap_or(default);
+    let status = Command::new(program).args(parts).arg(path).status()?;
+    // explorer.exe reports a failure status even when it opens the folder
+    if !status.success() && !cfg!(windows) {
+        return Err(std::io::Error::other(format!("'{}' exited with {}", program, status)));
+    }
+    Ok(())
+}
+
+/// Whether to emit ANSI colors on a stream, honoring `NO_COLOR` and `GCD_NO_COLOR`.
+fn color_enabled(is_terminal: bool) -> bool {
+    let disabled = |var| std::env::var_os(var).is_some_and(|v| !v.is_empty());
+    is_terminal && !disabled("NO_COLOR") && !disabled("GCD_NO_COLOR")
+}
+
+/// Bold and underline the characters of `text` at `indices` (char positions).
+fn highlight(text: &str, indices: &[usize]) -> String {
+    let mut out = String::new();
+    let mut active = false;
+    for (i, c) in text.chars().enumerate() {
+        let matched = indices.contains(&i);
+        if matched != active {
+            out.push_str(if matched { "\x1b[1;4m" } else { "\x1b[0m" });
+            active = matched;
+        }
+        out.push(c);
+    }
+    if active {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// Number of candidates per page of the selection menu when neither
+/// `--max-results` nor `max_results` in the config says otherwise.
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Print a numbered menu of candidates to stderr, `page_size` at a time, and
+/// read the chosen index from stdin. Entering `n` or `p` moves to the next or
+/// previous page and an empty line picks the best match. Returns `None` if
+/// the selection is invalid.
+fn select_interactively(
+    candidates: &[(i64, &String, &PathBuf)],
+    pattern: Option<&str>,
+    options: &MatchOptions,
+    page_size: usize,
+) -> Option<usize> {
+    let mut stderr = std::io::stderr();
+    let color = color_enabled(stderr.is_terminal());
+    let matcher = options.matcher();
+    let pages = candidates.len().div_ceil(page_size);
+    let mut page = 0;
+    loop {
+        let start = page * page_size;
+        let shown = &candidates[start..candidates.len().min(start + page_size)];
+        for (i, (_, name, path)) in shown.iter().enumerate() {
+            let name = match pattern.filter(|_| color) {
+                Some(pattern) => {
+                    let indices = matcher
+                        .fuzzy_indices(name, pattern)
+                        .map(|(_, indices)| indices)
+                        .unwrap_or_default();
+                    highlight(name, &indices)
+                }
+                None => name.to_string(),
+            };
+            let _ = writeln!(stderr, "{:>3}) {}: {}", start + i + 1, name, path.display());
+        }
+        if pages > 1 {
+            let _ = write!(
+                stderr,
+                "Page {} of {}; select a repository [1-{}], or n/p for the next/previous page: ",
+                page + 1,
+                pages,
+                candidates.len()
+            );
+        } else {
+            let _ = write!(stderr, "Select a repository [1-{}]: ", candidates.len());
+        }
+        let _ = stderr.flush();
+
+        let mut input = String::new();
+        std::io::stdin().lock().read_line(&mut input).ok()?;
+        match input.trim() {
+            "" => return Some(0),
+            "n" if pages > 1 => page = (page + 1).min(pages - 1),
+            "p" if pages > 1 => page = page.saturating_sub(1),
+            input => {
+                return match input.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= candidates.len() => Some(n - 1),
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
+/// How the shell integration enters the resolved repository.
+#[derive(Clone, Copy, ValueEnum)]
+enum IntegrationMode {
+    /// Change directory in the current shell
+    Cd,
+    /// Push the directory onto the directory stack
+    Pushd,
+    /// Start a nested shell rooted at the repository
+    Subshell,
+}
+
+/// Shells `gcd install` knows how to integrate with.
+const SUPPORTED_SHELLS: &[&str] = &["bash", "zsh", "fish", "ps", "nu", "xonsh"];
+
+/// The line substituted for `{{enter}}` in the integration template of
+/// `shell`, or `None` if the shell has no equivalent for `mode`.
+fn enter_command(shell: &str, mode: IntegrationMode) -> Option<&'static str> {
+    let command = match (shell, mode) {
+        ("fish", IntegrationMode::Cd) => "cd $output",
+        ("fish", IntegrationMode::Pushd) => "pushd $output",
+        ("fish", IntegrationMode::Subshell) => {
+            r#"fish -C (string join '; ' "cd "(string escape -- $output) $hooks); return"#
+        }
+        ("ps", IntegrationMode::Cd) => "Set-Location $output",
+        ("ps", IntegrationMode::Pushd) => "Push-Location $output",
+        ("ps", IntegrationMode::Subshell) => {
+            r#"& (Get-Process -Id $PID).Path -NoExit -Command "Set-Location -LiteralPath '$output'""#
+        }
+        ("nu", IntegrationMode::Cd) => "cd $output",
+        ("nu", IntegrationMode::Pushd) => return None,
+        ("nu", IntegrationMode::Subshell) => "do { cd $output; ^$nu.current-exe }",
+        ("xonsh", IntegrationMode::Cd) => "cd @(output)",
+        ("xonsh", IntegrationMode::Pushd) => "pushd @(output)",
+        ("xonsh", IntegrationMode::Subshell) => r#"subprocess.run(["xonsh"], cwd=output)"#,
+        (_, IntegrationMode::Cd) => r#"cd "$output" || return 1"#,
+        (_, IntegrationMode::Pushd) => r#"pushd "$output" > /dev/null || return 1"#,
+        (_, IntegrationMode::Subshell) => {
+            r#"(cd "$output" && eval "$hooks" && "${SHELL:-/bin/sh}"); return"#
+        }
+    };
+    Some(command)
+}
+
+/// Guess the user's shell from `$SHELL`, or PowerShell on Windows.
+fn detect_shell() -> Option<String> {
+    if let Some(shell) = std::env::var_os("SHELL") {
+        let name = Path::new(&shell).file_stem()?.to_string_lossy().to_string();
+        return match name.as_str() {
+            "pwsh" | "powershell" => Some("ps".to_string()),
+            _ if SUPPORTED_SHELLS.contains(&name.as_str()) => Some(name),
+            _ => None,
+        };
+    }
+    if cfg!(windows) && std::env::var_os("PSModulePath").is_some() {
+        return Some("ps".to_string());
+    }
+    None
+}
+
+/// The integration template for `shell`.
+fn integration_template(shell: &str) -> Result<&'static str, GcdError> {
+    match shell {
+        // zsh understands the same function syntax as bash
+        "bash" | "zsh" => Ok(BASH_INTEGRATION),
+        "fish" => Ok(FISH_INTEGRATION),
+        "ps" => Ok(POWERSHELL_INTEGRATION),
+        "nu" => Ok(NU_INTEGRATION),
+        "xonsh" => Ok(XONSH_INTEGRATION),
+        _ => Err(GcdError::UnsupportedShell {
+            shell: shell.to_string(),
+            supported: SUPPORTED_SHELLS,
+        }),
+    }
+}
+
+/// The integration function for `shell`, defined as `name`, with
+/// `{{enter}}` filled in for `mode`.
+fn render_integration(shell: &str, mode: IntegrationMode, name: &str) -> Result<String, GcdError> {
+    let template = integration_template(shell)?;
+    let enter = enter_command(shell, mode)
+        .ok_or_else(|| GcdError::Usage(format!("this mode is not supported for {}", shell)))?;
+    Ok(template.replace("{{enter}}", enter).replace("{{name}}", name))
+}
+
+/// Check a `--command-name`: it has to work as a function name in every
+/// supported shell.
+fn parse_command_name(name: &str) -> Result<String, String> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(format!(
+            "'{}' can't be used as a command name; use letters, digits, '_' and '-', starting with a letter or '_'",
+            name
+        ))
+    }
+}
+
+/// The rc file integration for `shell` lives in, and its template.
+fn integration_target(shell: &str) -> Result<(PathBuf, &'static str), GcdError> {
+    let template = integration_template(shell)?;
+    let path = if shell == "ps" {
+        // Handle PowerShell specifically
+        let output = command_stdout(Command::new("powershell").args(["-NoProfile", "-Command", "echo $PROFILE"]))
+            .map_err(|e| GcdError::Io(format!("failed to get the PowerShell profile path: {}", e)))?;
+        let path_str = String::from_utf8_lossy(&output).trim().to_string();
+        if !path_str.is_empty() {
+            PathBuf::from(path_str)
+        } else {
+            let docs = std::env::var("USERPROFILE")
+                .map(PathBuf::from)
+                .ok()
+                .or_else(dirs::home_dir)
+                .ok_or(GcdError::HomeDir)?;
+            docs.join("Documents").join("WindowsPowerShell").join("Microsoft.PowerShell_profile.ps1")
+        }
+    } else {
+        let home_dir = dirs::home_dir().ok_or(GcdError::HomeDir)?;
+        match shell {
+            "bash" => home_dir.join(".bashrc"),
+            "zsh" => home_dir.join(".zshrc"),
+            "fish" => home_dir.join(".config").join("fish").join("config.fish"),
+            "nu" => home_dir.join(".config").join("nushell").join("config.nu"),
+            "xonsh" => home_dir.join(".xonshrc"),
+            _ => unreachable!("integration_template accepted an unknown shell"),
+        }
+    };
+    Ok((path, template))
+}
+
+/// Marker line preceding the integration block in rc files. Bump
+/// `INTEGRATION_VERSION` whenever the templates change so that re-running
+/// `install` replaces blocks written by older versions.
+const INTEGRATION_MARKER: &str = "### GCD Integration";
+const INTEGRATION_VERSION: u32 = 4;
+
+/// What `install_shell_integration` or `uninstall_shell_integration` did to
+/// the rc file.
+enum IntegrationChange {
+    Installed,
+    Updated,
+    Removed,
+    Unchanged,
+    /// `confirm` turned the change down
+    Declined,
+}
+
+/// Tell the user which rc file is about to change and, when stdin is a
+/// terminal and `yes` wasn't given, ask them to confirm.
+fn confirm_rc_change(action: &str, yes: bool, quiet: bool) -> bool {
+    if yes || !std::io::stdin().is_terminal() {
+        inform(quiet, action);
+        return true;
+    }
+    eprint!("{}. Continue? [y/N] ", action);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Ask whether the fuzzy match `key` for `name` should be removed. Without a
+/// terminal to ask on, only `yes` removes it.
+fn confirm_removal(name: &str, key: &str, yes: bool) -> bool {
+    if yes {
+        return true;
+    }
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+    eprint!("'{}' matches repository '{}'. Remove it? [y/N] ", name, key);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Groups of names indexed for the same directory, each sorted by name, with
+/// paths compared after resolving symlinks where the directory still exists.
+fn duplicate_repos(config: &Config) -> Vec<(PathBuf, Vec<String>)> {
+    let mut by_path: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for (name, entry) in &config.repos {
+        let path = entry.path.canonicalize().unwrap_or_else(|_| entry.path.clone());
+        by_path.entry(path).or_default().push(name.clone());
+    }
+    let mut groups: Vec<_> = by_path.into_iter().filter(|(_, names)| names.len() > 1).collect();
+    for (_, names) in &mut groups {
+        names.sort();
+    }
+    groups.sort();
+    groups
+}
+
+/// Pick which of `names`, all indexed for `path`, `gcd prune-duplicates`
+/// keeps. Without `keep`, the user is asked on stderr.
+fn name_to_keep(config: &Config, path: &Path, names: &[String], keep: Option<KeepName>) -> Option<String> {
+    let chosen = match keep {
+        Some(KeepName::Shortest) => names.iter().min_by_key(|name| name.chars().count()),
+        Some(KeepName::Longest) => names.iter().min_by_key(|name| std::cmp::Reverse(name.chars().count())),
+        // Ties go to the alphabetically first name in every case
+        Some(KeepName::First) => names.iter().min_by_key(|name| config.repos[*name].added_at),
+        None => {
+            eprintln!("{} is indexed as:", path.display());
+            for (i, name) in names.iter().enumerate() {
+                eprintln!("{:>3}) {}", i + 1, name);
+            }
+            eprint!("Keep which name? [1-{}] ", names.len());
+            let _ = std::io::stderr().flush();
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer).ok()?;
+            let answer = answer.trim();
+            if answer.is_empty() {
+                names.first()
+            } else {
+                answer.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| names.get(i))
+            }
+        }
+    };
+    chosen.cloned()
+}
+
+/// Add the integration block to the shell's rc file, or bring an existing
+/// block up to date. `confirm` is asked, with a description of the change,
+/// before the file is written.
+fn install_shell_integration(
+    shell: &str,
+    mode: IntegrationMode,
+    name: &str,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<IntegrationChange, GcdError> {
+    let script = integration_target(shell)?;
+    let block = format!(
+        "{} v{}\n{}",
+        INTEGRATION_MARKER,
+        INTEGRATION_VERSION,
+        render_integration(shell, mode, name)?
+    );
+
+    let mut content = std::fs::read_to_string(&script.0).unwrap_or_default();
+    let change = if content.contains(INTEGRATION_MARKER) {
+        let (marker, end) = find_integration_block(&content, script.1).ok_or_else(|| {
+            GcdError::Io(format!(
+                "found the GCD marker in '{}' but not the end of its block; remove it by hand",
+                script.0.display()
+            ))
+        })?;
+        if content[marker..end] == block {
+            return Ok(IntegrationChange::Unchanged);
+        }
+        content.replace_range(marker..end, &block);
+        IntegrationChange::Updated
+    } else {
+        content.push('\n');
+        content.push_str(&block);
+        IntegrationChange::Installed
+    };
+    let action = match change {
+        IntegrationChange::Updated => format!("About to update the GCD integration in '{}'", script.0.display()),
+        _ => format!("About to append the GCD integration to '{}'", script.0.display()),
+    };
+    if !confirm(&action) {
+        return Ok(IntegrationChange::Declined);
+    }
+
+    let written = match script.0.parent() {
+        Some(parent) => std::fs::create_dir_all(parent).and_then(|_| std::fs::write(&script.0, content)),
+        None => std::fs::write(&script.0, content),
+    };
+    written.map_err(|e| GcdError::Io(format!("failed to write '{}': {}", script.0.display(), e)))?;
+    Ok(change)
+}
+
+/// Locate the integration block in an rc file, returning the offsets of the
+/// marker line and of the end of the closing line of the function that
+/// follows it (the last line of `template`, with any function name the
+/// block was installed under in place of `{{name}}`).
+fn find_integration_block(content: &str, template: &str) -> Option<(usize, usize)> {
+    let marker = content.find(INTEGRATION_MARKER)?;
+    let closing = template.trim_end().lines().last()?;
+    let is_closing = |line: &str| match closing.split_once("{{name}}") {
+        Some((prefix, suffix)) => {
+            line.len() > prefix.len() + suffix.len() && line.starts_with(prefix) && line.ends_with(suffix)
+        }
+        None => line == closing,
+    };
+    let mut offset = marker;
+    for line in content[marker..].split_inclusive('\n') {
+        offset += line.len();
+        if is_closing(line.trim_end_matches(['\r', '\n'])) {
+            return Some((marker, offset));
+        }
+    }
+    None
+}
+
+/// Remove the integration block for `shell`, leaving the rest of the rc file
+/// untouched. Returns whether anything was removed.
+fn uninstall_shell_integration(
+    shell: &str,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<IntegrationChange, GcdError> {
+    let (path, template) = integration_target(shell)?;
+    let mut content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(IntegrationChange::Unchanged),
+        Err(e) => return Err(GcdError::Io(format!("failed to read '{}': {}", path.display(), e))),
+    };
+    if !content.contains(INTEGRATION_MARKER) {
+        return Ok(IntegrationChange::Unchanged);
+    }
+    let (marker, end) = find_integration_block(&content, template).ok_or_else(|| {
+        GcdError::Io(format!(
+            "found the GCD marker in '{}' but not the end of its block; remove it by hand",
+            path.display()
+        ))
+    })?;
+
+    // Also take the newline `install` adds before the marker, unless doing so
+    // would join the preceding line onto whatever follows the block
+    let before = &content[..marker];
+    let start = if before.ends_with("\n\n") || (before.ends_with('\n') && end == content.len()) {
+        marker - 1
+    } else {
+        marker
+    };
+    content.replace_range(start..end, "");
+    if !confirm(&format!("About to remove the GCD integration from '{}'", path.display())) {
+        return Ok(IntegrationChange::Declined);
+    }
+    std::fs::write(&path, content)
+        .map_err(|e| GcdError::Io(format!("failed to write '{}': {}", path.display(), e)))?;
+    Ok(IntegrationChange::Removed)
+}
+
+fn print_completions(shell: &str) -> Result<(), GcdError> {
+    let (generator, dynamic) = match shell {
+        "bash" => (Shell::Bash, Some(BASH_COMPLETION)),
+        "zsh" => (Shell::Zsh, Some(ZSH_COMPLETION)),
+        "fish" => (Shell::Fish, Some(FISH_COMPLETION)),
+        "ps" | "powershell" => (Shell::PowerShell, None),
+        _ => {
+            return Err(GcdError::UnsupportedShell {
+                shell: shell.to_string(),
+                supported: &["bash", "zsh", "fish", "ps"],
+            })
+        }
+    };
+    clap_complete::generate(generator, &mut Cli::command(), "gcd", &mut std::io::stdout());
+    if let Some(dynamic) = dynamic {
+        print!("{}", dynamic);
+    }
+    Ok(())
+}
+
+// Completion snippets appended to the static clap completions so that the
+// first argument also completes indexed repository names.
+const BASH_COMPLETION: &str = r#"
+_gcd_with_repos() {
+    _gcd "$@"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY+=($(compgen -W "$(command gcd --list-names)" -- "${COMP_WORDS[COMP_CWORD]}"))
+    fi
+}
+complete -F _gcd_with_repos -o bashdefault -o default gcd
+"#;
+
+const ZSH_COMPLETION: &str = r#"
+_gcd_with_repos() {
+    _gcd "$@"
+    if (( CURRENT == 2 )); then
+        local -a repos
+        repos=(${(f)"$(command gcd --list-names)"})
+        compadd -a repos
+    fi
+}
+compdef _gcd_with_repos gcd
+"#;
+
+const FISH_COMPLETION: &str = r#"
+complete -c gcd -n "__fish_use_subcommand" -f -a "(command gcd --list-names)"
+"#;
+
+// Integration templates; `{{enter}}` is replaced at install time with the
+// command that enters the resolved directory (see `enter_command`), and
+// `{{name}}` with the name of the function (`--command-name`).
+//
+// The bash/zsh and fish wrappers set `GCD_HOOKS`, asking gcd to start
+// navigation output with `NAVIGATION_MARKER`, followed by the directory and
+// any hook commands. Hooks are evaluated once the directory has been
+// entered; output without the marker (e.g. from `list` or `which`) is
+// echoed as is.
+const BASH_INTEGRATION: &str = r#"
+{{name}}() {
+    if [ "$#" -eq 0 ]; then
+        command gcd
+    else
+        local output hooks=""
+        output=$(GCD_HOOKS=1 command gcd "$@")
+        if [ $? -ne 0 ]; then
+            echo "$output"
+            return 1
+        fi
+        if [ "${output%%$'\n'*}" != $'\x1egcd-cd' ]; then
+            echo "$output"
+            return 0
+        fi
+        output=${output#*$'\n'}
+        case $output in
+            *$'\n'*) hooks=${output#*$'\n'}; output=${output%%$'\n'*} ;;
+        esac
+        {{enter}}
+        [ -z "$hooks" ] || eval "$hooks"
+    fi
+}
+"#;
+
+const FISH_INTEGRATION: &str = r#"
+function {{name}}
+    if test (count $argv) -eq 0
+        command gcd
+    else
+        set -l output (GCD_HOOKS=1 command gcd $argv)
+        if test $status -ne 0
+            printf '%s\n' $output
+            return 1
+        end
+        if test "$output[1]" != \x1egcd-cd
+            printf '%s\n' $output
+            return 0
+        end
+        set -l hooks $output[3..-1]
+        set output $output[2]
+        {{enter}}
+        for hook in $hooks
+            eval $hook
+        end
+    end
+end
+"#;
+
+const POWERSHELL_INTEGRATION: &str = r#"
+function {{name}} {
+    if ($args.Count -eq 0) {
+        & gcd.exe
+    } else {
+        $output = & gcd.exe $args
+        if ($LASTEXITCODE -eq 0) {
+            {{enter}}
+        } else {
+            Write-Host $output
+            return $LASTEXITCODE
+        }
+    }
+}
+"#;
+
+const NU_INTEGRATION: &str = r#"
+def --env --wrapped {{name}} [...args] {
+    if ($args | is-empty) {
+        ^gcd
+    } else {
+        let result = (do { ^gcd ...$args } | complete)
+        let output = ($result.stdout | str trim)
+        if $result.exit_code == 0 {
+            {{enter}}
+        } else {
+            print $output
+            print -e ($result.stderr | str trim)
+            return
+        }
+    }
+}
+"#;
+
+const XONSH_INTEGRATION: &str = r#"
+def _gcd(args):
+    import subprocess
+    if not args:
+        subprocess.run(["gcd"])
+        return
+    result = subprocess.run(["gcd", *args], stdout=subprocess.PIPE, text=True)
+    output = result.stdout.strip()
+    if result.returncode == 0:
+        {{enter}}
+    else:
+        print(output)
+        return 1
+
+aliases["{{name}}"] = _gcd
+"#;
+
+/// Most patterns `--cache-matches` remembers; past this the cache starts over.
+const MATCH_CACHE_LEN: usize = 256;
+
+/// Scored candidates for a pattern, and whether they are fuzzy matches that
+/// [`rank_matches`] still has to sort.
+type Candidates<'a> = (Vec<(i64, &'a String, &'a PathBuf)>, bool);
+
+/// Candidates found by earlier runs for `--cache-matches`, stored next to
+/// the config and valid only as long as the index is unchanged.
+#[derive(Default, Serialize, Deserialize)]
+struct MatchCache {
+    /// Hash of the config the candidates were found in, leaving out the
+    /// visits every navigation records
+    index_hash: Option<u64>,
+    /// Whether the candidates are fuzzy matches, and their scores and
+    /// names, keyed by pattern and matching flags
+    entries: BTreeMap<String, (bool, Vec<(i64, String)>)>,
+}
+
+impl MatchCache {
+    fn path() -> PathBuf {
+        let mut path = config_path().into_os_string();
+        path.push(".matches");
+        PathBuf::from(path)
+    }
+
+    /// A hash of everything in the config that can change which candidates
+    /// a pattern finds. Visits only order the candidates, which is redone
+    /// on every run, so recording one keeps the cache valid.
+    fn index_hash(config: &Config) -> Option<u64> {
+        use std::hash::{Hash, Hasher};
+        fn strip_visits(fields: &mut serde_json::Value) {
+            if let Some(fields) = fields.as_object_mut() {
+                for key in ["visits", "last_visited", "history"] {
+                    fields.remove(key);
+                }
+            }
+        }
+        let mut index = config.loaded.clone();
+        strip_visits(&mut index);
+        if let Some(profiles) = index.get_mut("profiles").and_then(|p| p.as_object_mut()) {
+            profiles.values_mut().for_each(strip_visits);
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        serde_json::to_string(&index).ok()?.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// The cache for the config as it is now, empty if it was written for an
+    /// older version of the index or can't be read.
+    fn load(config: &Config) -> Self {
+        let index_hash = Self::index_hash(config);
+        let cache = std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<MatchCache>(&contents).ok())
+            .filter(|cache| index_hash.is_some() && cache.index_hash == index_hash);
+        cache.unwrap_or(MatchCache {
+            index_hash,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    /// The candidates remembered under `key`, unless one of them has since
+    /// left the index.
+    fn get<'a>(&self, key: &str, config: &'a Config) -> Option<Candidates<'a>> {
+        let (fuzzy, names) = self.entries.get(key)?;
+        let matches = names
+            .iter()
+            .map(|(score, name)| {
+                let (name, entry) = config.repos.get_key_value(name)?;
+                Some((*score, name, &entry.path))
+            })
+            .collect::<Option<_>>()?;
+        Some((matches, *fuzzy))
+    }
+
+    /// Remember `matches` under `key` and write the cache back. Failing to
+    /// write it only costs the next run some time, so it isn't reported.
+    /// Patterns that matched nothing aren't remembered, so that a rescan
+    /// (or `--auto-index`) finds newly indexed repositories straight away.
+    fn insert(&mut self, key: String, matches: &[(i64, &String, &PathBuf)], fuzzy: bool) {
+        if self.index_hash.is_none() || matches.is_empty() {
+            return;
+        }
+        if self.entries.len() >= MATCH_CACHE_LEN {
+            self.entries.clear();
+        }
+        let names = matches.iter().map(|(score, name, _)| (*score, (*name).clone())).collect();
+        self.entries.insert(key, (fuzzy, names));
+        let Ok(contents) = serde_json::to_string(self) else {
+            return;
+        };
+        // Renamed into place so that concurrent runs never read half a file
+        let path = Self::path();
+        let mut temp = path.clone().into_os_string();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        if std::fs::write(&temp, contents).and_then(|_| std::fs::rename(&temp, &path)).is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+    }
+}
+
+/// The repositories `pattern` resolves to under the matching mode selected
+/// on the command line, best first, reusing earlier candidates with
+/// `--cache-matches`.
+fn resolve_matches<'a>(
+    cli: &Cli,
+    config: &'a Config,
+    pattern: &str,
+    match_options: &MatchOptions,
+) -> Result<Vec<(i64, &'a String, &'a PathBuf)>, GcdError> {
+    let Some(cache) = &config.match_cache else {
+        let (matches, fuzzy) = find_candidates(cli, config, pattern, match_options)?;
+        return Ok(rank_matches(cli, config, matches, fuzzy));
+    };
+    // Everything else that decides the candidates lives in the config
+    // itself; `--limit` and `--recent` only rank them
+    let key = serde_json::to_string(&(
+        config.profile_name(),
+        pattern,
+        (cli.exact, cli.regex, cli.glob, cli.path, cli.case_sensitive),
+        (&cli.tag, &cli.exclude, &cli.branch),
+        (cli.min_score, cli.matcher),
+    ))
+    .unwrap_or_default();
+    let cached = cache.borrow().get(&key, config);
+    let (matches, fuzzy) = match cached {
+        Some(cached) => cached,
+        None => {
+            let (matches, fuzzy) = find_candidates(cli, config, pattern, match_options)?;
+            cache.borrow_mut().insert(key, &matches, fuzzy);
+            (matches, fuzzy)
+        }
+    };
+    Ok(rank_matches(cli, config, matches, fuzzy))
+}
+
+/// The candidates `pattern` resolves to, computed afresh. Nothing here
+/// depends on visits, so the result can be cached across navigations.
+fn find_candidates<'a>(
+    cli: &Cli,
+    config: &'a Config,
+    pattern: &str,
+    match_options: &MatchOptions,
+) -> Result<Candidates<'a>, GcdError> {
+    let alias = || {
+        let target = config.aliases.get(pattern)?;
+        config.repos.get_key_value(target)
+    };
+    // Names given exactly, directly or through an alias, are never excluded
+    let matcher = match_options.matcher();
+    let excluded = |name: &str| {
+        cli.exclude
+            .iter()
+            .any(|exclude| matcher.fuzzy_match(name, exclude).is_some())
+    };
+    let branch_score = |name: &str| match &cli.branch {
+        Some(branch) => {
+            let current = config.repos.get(name)?.current_branch.as_deref()?;
+            matcher.fuzzy_match(current, branch)
+        }
+        None => Some(0),
+    };
+    let tagged = |name: &str| config.has_tag(name, cli.tag.as_deref()) && branch_score(name).is_some();
+    let matches = if pattern.is_empty() && cli.branch.is_some() {
+        // Matched by branch alone, best first
+        let mut matches: Vec<_> = config
+            .repos
+            .iter()
+            .filter(|(name, _)| tagged(name) && !excluded(name))
+            .filter_map(|(name, entry)| Some((branch_score(name)?, name, &entry.path)))
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        matches
+    } else if cli.exact {
+        config
+            .repos
+            .get_key_value(pattern)
+            .or_else(alias)
+            .filter(|(name, _)| tagged(name))
+            .map(|(name, entry)| (0, name, &entry.path))
+            .into_iter()
+            .collect()
+    } else if cli.regex {
+        let regex = regex::Regex::new(pattern)
+            .map_err(|e| GcdError::Usage(format!("invalid regex '{}': {}", pattern, e)))?;
+        filter_matches(&config.repos, |name| regex.is_match(name) && tagged(name) && !excluded(name))
+    } else if cli.glob {
+        let glob = Glob::new(pattern)
+            .map_err(|e| GcdError::Usage(format!("invalid glob '{}': {}", pattern, e)))?
+            .compile_matcher();
+        filter_matches(&config.repos, |name| glob.is_match(name) && tagged(name) && !excluded(name))
+    } else if let Some((name, entry)) = alias().filter(|(name, _)| tagged(name)) {
+        vec![(0, name, &entry.path)]
+    } else {
+        let mut matches = scored_matches(&config.repos, pattern, match_options);
+        matches.retain(|m| tagged(m.1) && !excluded(m.1));
+
+        // Only offer candidates that score reasonably close to the best
+        // match. Dropping the rest before sorting keeps large indexes cheap.
+        if let Some(best) = matches.iter().map(|m| m.0).max() {
+            matches.retain(|m| m.0.saturating_mul(2) >= best);
+        }
+        return Ok((matches, true));
+    };
+    Ok((matches, false))
+}
+
+/// Sort fuzzy `candidates` best first, if `fuzzy`, and apply `--limit`.
+fn rank_matches<'a>(
+    cli: &Cli,
+    config: &Config,
+    mut matches: Vec<(i64, &'a String, &'a PathBuf)>,
+    fuzzy: bool,
+) -> Vec<(i64, &'a String, &'a PathBuf)> {
+    if fuzzy {
+        // Break score ties in favour of the most recently modified
+        // directory when asked to, then the most visited repository, then
+        // the name and path. Directories are only statted for candidates
+        // that actually tie.
+        let prefer_recent = cli.recent || config.prefer_recent;
+        let mut mtimes: HashMap<PathBuf, SystemTime> = HashMap::new();
+        let mut mtime = |path: &PathBuf| {
+            *mtimes.entry(path.clone()).or_insert_with(|| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(unknown_time())
+            })
+        };
+        let visits = |name: &String| config.visits.get(name).copied().unwrap_or(0);
+        let mut compare = |a: &(i64, &String, &PathBuf), b: &(i64, &String, &PathBuf)| {
+            b.0.cmp(&a.0)
+                .then_with(|| {
+                    if prefer_recent {
+                        mtime(b.2).cmp(&mtime(a.2))
+                    } else {
+                        std::cmp::Ordering::Equal
+                    }
+                })
+                .then_with(|| visits(b.1).cmp(&visits(a.1)))
+                .then_with(|| a.1.cmp(b.1))
+                .then_with(|| a.2.cmp(b.2))
+        };
+        // With a limit, only the candidates that survive it need sorting
+        if let Some(limit) = cli.limit.filter(|limit| *limit > 0 && *limit < matches.len()) {
+            matches.select_nth_unstable_by(limit - 1, &mut compare);
+            matches.truncate(limit);
+        }
+        matches.sort_by(&mut compare);
+    }
+    if let Some(limit) = cli.limit {
+        matches.truncate(limit);
+    }
+    matches
+}
+
+/// Split a pattern like `app/src/api` into the part naming a repository and
+/// a subdirectory to enter inside it. A split is used if it leads to an
+/// existing directory; failing that, the whole pattern is tried as a
+/// repository (e.g. `work/api` matched against paths), and only then the
+/// first segment, leaving it to [`enter_subpath`] to warn about the rest.
+fn split_subpath<'p>(
+    cli: &Cli,
+    config: &Config,
+    pattern: &'p str,
+    match_options: &MatchOptions,
+) -> (&'p str, Option<&'p str>) {
+    if cli.regex || cli.glob {
+        return (pattern, None);
+    }
+    let splits: Vec<_> = pattern
+        .match_indices('/')
+        .map(|(at, _)| (&pattern[..at], &pattern[at + 1..]))
+        .filter(|(repo, rest)| !repo.is_empty() && !rest.trim_matches('/').is_empty())
+        .collect();
+    // Only regex and glob patterns can fail to resolve, and those aren't split
+    let top = |pattern: &str| {
+        resolve_matches(cli, config, pattern, match_options)
+            .ok()?
+            .first()
+            .map(|(_, _, path)| (*path).clone())
+    };
+
+    // Prefer the longest repository part, so `teamA/frontend/src` works
+    // with names that contain a `/` themselves
+    for &(repo, rest) in splits.iter().rev() {
+        if top(repo).is_some_and(|path| path.join(rest).is_dir()) {
+            return (repo, Some(rest));
+        }
+    }
+    if top(pattern).is_some() {
+        return (pattern, None);
+    }
+    match splits.first() {
+        Some(&(repo, rest)) if top(repo).is_some() => (repo, Some(rest)),
+        _ => (pattern, None),
+    }
+}
+
+/// The directory to enter for `subpath` inside the repository `name`, or the
+/// repository root (with a warning) if it doesn't exist.
+fn enter_subpath(name: &str, root: &Path, subpath: Option<&str>) -> PathBuf {
+    let Some(subpath) = subpath else {
+        return root.to_path_buf();
+    };
+    let target = root.join(subpath);
+    if target.is_dir() {
+        target
+    } else {
+        eprintln!(
+            "Warning: '{}' does not exist in '{}'; using the repository root",
+            subpath, name
+        );
+        root.to_path_buf()
+    }
+}
+
+/// Split a trailing `@branch` off a pattern like `app@feature`, naming a
+/// worktree of the repository. Patterns that name a repository or alias
+/// exactly, `@` and all, are left alone.
+fn split_worktree<'p>(config: &Config, pattern: &'p str) -> (&'p str, Option<&'p str>) {
+    if config.repos.contains_key(pattern) || config.aliases.contains_key(pattern) {
+        return (pattern, None);
+    }
+    match pattern.rsplit_once('@') {
+        Some((repo, branch)) if !repo.is_empty() && !branch.is_empty() => (repo, Some(branch)),
+        _ => (pattern, None),
+    }
+}
+
+/// A worktree of a repository, with the short name of its branch unless
+/// its HEAD is detached.
+struct Worktree {
+    path: PathBuf,
+    branch: Option<String>,
+}
+
+/// The worktrees of the repository at `path`, the main one first, as listed
+/// by `git worktree list --porcelain`.
+fn worktrees(path: &Path) -> Vec<Worktree> {
+    let Some(stdout) = git_query(Some(path), &["worktree", "list", "--porcelain"]) else {
+        return Vec::new();
+    };
+    let mut worktrees: Vec<Worktree> = Vec::new();
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            worktrees.push(Worktree {
+                path: PathBuf::from(path),
+                branch: None,
+            });
+        } else if let (Some(branch), Some(worktree)) = (line.strip_prefix("branch "), worktrees.last_mut()) {
+            let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+            worktree.branch = Some(branch.to_string());
+        }
+    }
+    worktrees
+}
+
+/// The worktree of the repository `name` at `root` to enter for `branch`:
+/// the one on exactly that branch, otherwise the best fuzzy match. Without
+/// a branch, the repository itself.
+fn worktree_root(
+    name: &str,
+    root: &Path,
+    branch: Option<&str>,
+    match_options: &MatchOptions,
+) -> Result<PathBuf, GcdError> {
+    let Some(branch) = branch else {
+        return Ok(root.to_path_buf());
+    };
+    let worktrees = worktrees(root);
+    if let Some(worktree) = worktrees.iter().find(|worktree| worktree.branch.as_deref() == Some(branch)) {
+        return Ok(worktree.path.clone());
+    }
+    let matcher = match_options.matcher();
+    // Reversed so that ties go to the worktree listed first
+    worktrees
+        .iter()
+        .rev()
+        .filter_map(|worktree| Some((matcher.fuzzy_match(worktree.branch.as_deref()?, branch)?, worktree)))
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, worktree)| worktree.path.clone())
+        .ok_or_else(|| {
+            GcdError::NoMatch(format!("No worktree of '{}' has a branch matching '{}'", name, branch))
+        })
+}
+
+/// Resolve `pattern` to a repository, print its path for the shell
+/// integration to `cd` into, and record the visit.
+fn navigate(cli: &Cli, config: &mut Config, pattern: &str, match_options: &MatchOptions) -> Result<(), GcdError> {
+    let (pattern, worktree) = split_worktree(config, pattern);
+    let (pattern, subpath) = split_subpath(cli, config, pattern, match_options);
+    let fuzzy = !cli.exact && !cli.regex && !cli.glob;
+    let mut matches = resolve_matches(cli, config, pattern, match_options)?;
+    // Retried only once, however the rescan turns out
+    if matches.is_empty() && (cli.auto_index || config.auto_index) && !config.scan_paths.is_empty() {
+        eprintln!("No repository matched '{}'; reindexing to look for it", pattern);
+        let paths = config.scan_paths.clone();
+        let mut options = ScanOptions::from_config(config, cli);
+        options.quiet = true;
+        index_paths(config, &paths, &options)?;
+        if let Err(e) = config.save() {
+            eprintln!("Warning: failed to save the refreshed index: {}", e);
+        }
+        matches = resolve_matches(cli, config, pattern, match_options)?;
+    }
+    if cli.debug_score {
+        for (score, name, path) in &matches {
+            eprintln!("{:>6}  {}: {}", score, name, path.display());
+        }
+    }
+    let interactive = match cli.resolution.or(config.resolution) {
+        None => std::io::stdout().is_terminal(),
+        Some(Resolution::First) => false,
+        // The menu is drawn on stderr and read from stdin, so it works even
+        // when the shell integration captures stdout
+        Some(Resolution::Interactive) => {
+            std::io::stderr().is_terminal() && std::io::stdin().is_terminal()
+        }
+        Some(Resolution::Error) => {
+            if matches.len() > 1 {
+                let mut message = format!("Pattern '{}' is ambiguous; candidates:", pattern);
+                for (score, name, path) in &matches {
+                    message.push_str(&format!("\n{:>6}  {}: {}", score, name, path.display()));
+                }
+                return Err(GcdError::NoMatch(message));
+            }
+            false
+        }
+    };
+    let choice = if matches.len() > 1 && interactive {
+        let page_size = cli
+            .max_results
+            .or(config.max_results)
+            .filter(|size| *size > 0)
+            .map_or(DEFAULT_PAGE_SIZE, usize::from);
+        match select_interactively(&matches, fuzzy.then_some(pattern), match_options, page_size) {
+            Some(index) => index,
+            None => return Err(GcdError::NoMatch("Invalid selection".to_string())),
+        }
+    } else {
+        0
+    };
+
+    let (name, path) = match matches.get(choice) {
+        Some((score, name, path)) => {
+            if cli.verbose {
+                eprintln!("Matched '{}' with score {}: {}", name, score, path.display());
+            }
+            ((*name).clone(), (*path).clone())
+        }
+        None if cli.exact => return Err(GcdError::NoMatch(format!("No repository named '{}'", pattern))),
+        None => return Err(GcdError::NoMatch("No matching repository found".to_string())),
+    };
+
+    let root = worktree_root(&name, &path, worktree, match_options)?;
+    let target = enter_subpath(&name, &root, subpath);
+    let hook = config.hooks.get(&name).or(config.hook.as_ref());
+    emit_destination(cli, &target, hook.map(String::as_str));
+    *config.visits.entry(name.clone()).or_insert(0) += 1;
+    config.last_visited.insert(name, SystemTime::now());
+    config.record_history(&target);
+    if let Err(e) = config.save() {
+        eprintln!("Warning: failed to record visit: {}", e);
+    }
+    Ok(())
+}
+
+/// Print the path navigated to before the latest one, like `cd -`. Going
+/// back makes it the latest, so repeating `gcd -` toggles between the two.
+fn navigate_back(cli: &Cli, config: &mut Config) -> Result<(), GcdError> {
+    let Some(previous) = config.history.get(1).cloned() else {
+        return Err(GcdError::NoMatch("No previous repository to go back to".to_string()));
+    };
+    if !previous.is_dir() {
+        return Err(GcdError::NoMatch(format!(
+            "The previous repository no longer exists: {}",
+            previous.display()
+        )));
+    }
+    emit_destination(cli, &previous, None);
+    config.record_history(&previous);
+    if let Err(e) = config.save() {
+        eprintln!("Warning: failed to record visit: {}", e);
+    }
+    Ok(())
+}
+
+/// Outcome of one `gcd doctor` check, ordered from best to worst.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Health {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Run every `gcd doctor` check, reporting each on stderr, and return the
+/// worst outcome. The config is parsed without taking the lock or touching
+/// the file, so this is safe to run at any time.
+fn doctor(cli: &Cli) -> Health {
+    let mut results = Vec::new();
+    let mut report = |health: Health, message: String| {
+        let label = match health {
+            Health::Pass => "pass",
+            Health::Warn => "warn",
+            Health::Fail => "fail",
+        };
+        eprintln!("[{}] {}", label, message);
+        results.push(health);
+    };
+
+    let path = config_path();
+    let config = match std::fs::read_to_string(&path) {
+        Ok(contents) => match ConfigFormat::of(&path).parse(&contents) {
+            Ok(config) => {
+                report(Health::Pass, format!("Config '{}' parses", path.display()));
+                Some(config)
+            }
+            Err(e) => {
+                report(Health::Fail, format!("Config '{}' doesn't parse: {}", path.display(), e));
+                None
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let message = format!("Config '{}' doesn't exist yet; run `gcd index`", path.display());
+            report(Health::Warn, message);
+            Some(Config::default())
+        }
+        Err(e) => {
+            report(Health::Fail, format!("Config '{}' can't be read: {}", path.display(), e));
+            None
+        }
+    };
+
+    if let Some(mut config) = config {
+        if let Some(name) = cli.profile.as_deref().filter(|name| *name != DEFAULT_PROFILE) {
+            config.profile = Some(name.to_string());
+            config.swap_profile();
+        }
+        config.normalize_paths();
+        let missing = config.repos.values().filter(|entry| !entry.path.exists()).count();
+        let total = config.repos.len();
+        let profile = config.profile_name();
+        if total == 0 {
+            report(Health::Warn, format!("No repositories are indexed in profile '{}'", profile));
+        } else if missing > 0 {
+            let message = format!(
+                "{} of {} repositories in profile '{}' have missing paths; run `gcd clean`",
+                missing, total, profile
+            );
+            report(Health::Warn, message);
+        } else {
+            report(Health::Pass, format!("{} repositories indexed in profile '{}'", total, profile));
+        }
+
+        let options = MatchOptions::new(cli, &config);
+        let matcher = match options.matcher {
+            MatcherKind::Skim => "skim",
+            MatcherKind::Clangd => "clangd",
+        };
+        let resolution = match cli.resolution.or(config.resolution) {
+            None => "automatic",
+            Some(Resolution::First) => "first",
+            Some(Resolution::Interactive) => "interactive",
+            Some(Resolution::Error) => "error",
+        };
+        let min_score = options.min_score.map_or("none".to_string(), |min| min.to_string());
+        let case = if options.case_sensitive { "case-sensitive" } else { "ignoring case" };
+        let message = format!(
+            "Matching with {} ({}), minimum score {}, resolution {}",
+            matcher, case, min_score, resolution
+        );
+        report(Health::Pass, message);
+    }
+
+    match detect_shell() {
+        None => report(Health::Warn, "Could not detect your shell to check its integration".to_string()),
+        Some(shell) => match integration_target(&shell) {
+            Err(e) => report(Health::Warn, format!("Could not locate the {} rc file: {}", shell, e)),
+            Ok((rc, _)) => {
+                let content = std::fs::read_to_string(&rc).unwrap_or_default();
+                let version = content
+                    .lines()
+                    .find_map(|line| line.strip_prefix(INTEGRATION_MARKER))
+                    .map(|rest| rest.trim().trim_start_matches('v').parse::<u32>().unwrap_or(0));
+                match version {
+                    Some(INTEGRATION_VERSION) => {
+                        report(Health::Pass, format!("Shell integration is installed in '{}'", rc.display()))
+                    }
+                    Some(_) => report(
+                        Health::Warn,
+                        format!("Shell integration in '{}' is outdated; run `gcd install {}`", rc.display(), shell),
+                    ),
+                    None => report(
+                        Health::Warn,
+                        format!(
+                            "Shell integration isn't installed in '{}' (fine if you use `gcd init`)",
+                            rc.display()
+                        ),
+                    ),
+                }
+            }
+        },
+    }
+
+    let count = |health| results.iter().filter(|result| **result == health).count();
+    eprintln!(
+        "{} passed, {} warnings, {} failed",
+        count(Health::Pass),
+        count(Health::Warn),
+        count(Health::Fail)
+    );
+    results.into_iter().max().unwrap_or(Health::Pass)
+}
+
+/// The indexed repositories passing the `--tag` filter.
+fn tagged_repos<'a>(cli: &'a Cli, config: &'a Config) -> impl Iterator<Item = (&'a String, &'a RepoEntry)> {
+    config.repos.iter().filter(|(name, _)| config.has_tag(name, cli.tag.as_deref()))
+}
+
+/// Collect what `gcd __capabilities` prints from the command-line
+/// definition, so it can't drift from what is actually accepted.
+fn capabilities() -> Capabilities {
+    fn long_flags(command: &clap::Command) -> Vec<String> {
+        let mut flags: Vec<_> = command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set() && !arg.is_global_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{}", long))
+            .collect();
+        flags.sort();
+        flags
+    }
+
+    let cli = Cli::command();
+    let mut flags = long_flags(&cli);
+    flags.extend(
+        cli.get_arguments()
+            .filter(|arg| !arg.is_hide_set() && arg.is_global_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{}", long)),
+    );
+    flags.sort();
+    let subcommands = cli
+        .get_subcommands()
+        .filter(|command| !command.is_hide_set())
+        .map(|command| (command.get_name().to_string(), long_flags(command)))
+        .collect();
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        config_version: CONFIG_VERSION,
+        config_formats: &["json", "toml"],
+        integration_version: INTEGRATION_VERSION,
+        shells: SUPPORTED_SHELLS,
+        flags,
+        subcommands,
+    }
+}
+
+/// The listing printed by a bare `gcd`, as plain `name: path` lines or JSON.
+fn list_repos(cli: &Cli, config: &Config) -> Result<(), GcdError> {
+    if cli.json {
+        let mut repos: Vec<_> = tagged_repos(cli, config).collect();
+        repos.sort_by(|a, b| a.0.cmp(b.0));
+        let listing: Vec<_> = repos
+            .into_iter()
+            .take(cli.limit.unwrap_or(usize::MAX))
+            .map(|(name, entry)| RepoListing {
+                name,
+                path: shown_path(cli, &entry.path),
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&listing)
+            .map_err(|e| GcdError::Io(format!("failed to serialize repositories: {}", e)))?;
+        println!("{}", json);
+    } else {
+        if !cli.print0 {
+            match &config.profile {
+                Some(profile) => println!("Available repositories in profile '{}':", profile),
+                None => println!("Available repositories:"),
+            }
+        }
+        let mut repos: Vec<_> = tagged_repos(cli, config).collect();
+        repos.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, entry) in repos.into_iter().take(cli.limit.unwrap_or(usize::MAX)) {
+            emit(cli.print0, format!("{}: {}", name, shown_path(cli, &entry.path).display()));
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        match &e {
+            // Reported as is; an empty message (e.g. a cancelled picker) exits quietly
+            GcdError::NoMatch(message) if message.is_empty() => {}
+            GcdError::NoMatch(message) => eprintln!("{}", message),
+            _ => eprintln!("Error: {}", e),
+        }
+        e.status().exit();
+    }
+}
+
+fn run() -> Result<(), GcdError> {
+    let mut cli = Cli::parse();
+    // Resolved once here; indexed paths are canonical, so the base must be too
+    if let Some(base) = cli.relative.take() {
+        match base.canonicalize() {
+            Ok(base) => cli.relative = Some(base),
+            Err(e) => {
+                return Err(GcdError::Usage(format!(
+                    "cannot use '{}' as --relative base: {}",
+                    base.display(),
+                    e
+                )))
+            }
+        }
+    }
+    // Answered before loading, so it works even when the config is unreadable
+    if let Some(Commands::ConfigPath) = cli.command {
+        emit_path(cli.print0, &config_path());
+        return Ok(());
+    }
+    if let Some(Commands::Capabilities) = cli.command {
+        let json = serde_json::to_string_pretty(&capabilities())
+            .map_err(|e| GcdError::Io(format!("failed to serialize capabilities: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    // Also before loading, so that an unreadable config can be diagnosed
+    if let Some(Commands::Doctor) = cli.command {
+        if doctor(&cli) == Health::Fail {
+            Exit::Io.exit();
+        }
+        return Ok(());
+    }
+    let read_only = cli.no_config_write
+        || std::env::var_os("GCD_NO_CONFIG_WRITE").is_some_and(|v| !v.is_empty());
+    ConfigFormat::from_env()?;
+    let mut config = Config::load(cli.reset_config, cli.profile.as_deref(), read_only)?;
+    // The cache is written next to the config, so it's off along with writes
+    if cli.cache_matches && !read_only {
+        config.match_cache = Some(RefCell::new(MatchCache::load(&config)));
+    }
+    let match_options = MatchOptions::new(&cli, &config);
+
+    match cli.command.take() {
+        Some(Commands::Index {
+            paths,
+            depth,
+            ignore,
+            no_follow_links,
+            threads,
+            name_from_remote,
+            name_from,
+            stdin,
+            dry_run,
+            full,
+            check,
+            new_only,
+            remote_filter,
+            no_hidden,
+            branches,
+            include_submodules,
+        }) => {
+            let mut options = ScanOptions::from_config(&config, &cli);
+            options.depth = depth;
+            options.ignore_dirs.extend(ignore);
+            options.follow_links = !no_follow_links;
+            options.threads = threads;
+            options.name_from_remote = name_from_remote;
+            options.name_segments = name_from.into();
+            options.full = full;
+            options.check = check;
+            options.new_only = new_only;
+            options.remote_filter = remote_filter;
+            options.skip_hidden = no_hidden;
+            options.branches |= branches;
+            options.submodules = include_submodules;
+            let known: HashSet<_> = config.repos.values().map(|entry| entry.path.clone()).collect();
+            let keys = if stdin {
+                let lines = std::io::stdin().lock().lines().map_while(Result::ok);
+                import_repo_paths(&mut config, lines, &options)
+            } else {
+                index_paths(&mut config, &paths, &options)?
+            };
+            if keys.is_empty() {
+                return Err(GcdError::NoMatch("No repositories were indexed".to_string()));
+            }
+            if new_only {
+                let (existing, added): (Vec<_>, Vec<_>) =
+                    keys.into_iter().partition(|key| known.contains(&config.repos[key].path));
+                if dry_run {
+                    for key in &added {
+                        println!("{} -> {}", key, config.repos[key].path.display());
+                    }
+                    println!(
+                        "Would index {} new repositories ({} already indexed)",
+                        added.len(),
+                        existing.len()
+                    );
+                } else {
+                    config.save_changes()?;
+                    let summary = format!(
+                        "Indexed {} new repositories, skipped {} already indexed",
+                        added.len(),
+                        existing.len()
+                    );
+                    inform(cli.quiet, summary);
+                }
+            } else if dry_run {
+                // The index was only updated in memory; leave the config untouched
+                for key in &keys {
+                    println!("{} -> {}", key, config.repos[key].path.display());
+                }
+                println!("Would index {} repositories", keys.len());
+            } else {
+                config.save_changes()?;
+                inform(cli.quiet, format!("Indexed {} repositories successfully", keys.len()));
+            }
+        }
+        Some(Commands::Add { path, name, force }) => {
+            let path = path.unwrap_or_else(|| PathBuf::from("."));
+            let path = path
+                .canonicalize()
+                .map_err(|e| GcdError::Io(format!("cannot add '{}': {}", path.display(), e)))?;
+            if !is_git_repo(&path) && !is_bare_repo(&path) {
+                return Err(GcdError::Usage(format!("'{}' is not a git repository", path.display())));
+            }
+            let Some(name) = name else {
+                let key = config.insert_repo(repo_name(&path), RepoEntry::scan(path)).into_key();
+                config.save_changes()?;
+                inform(cli.quiet, format!("Added repository '{}'", key));
+                return Ok(());
+            };
+            if let Some(existing) = config.repos.get(&name).filter(|existing| existing.path != path) {
+                if !force {
+                    return Err(GcdError::Usage(format!(
+                        "repository '{}' already exists at '{}' (use --force to overwrite)",
+                        name,
+                        existing.path.display()
+                    )));
+                }
+                config.remove_repo(&name);
+            }
+            // `name` is now free or already holds this path, so an existing
+            // entry for the path comes back under its own key
+            let key = config.insert_repo(name.clone(), RepoEntry::scan(path)).into_key();
+            if key != name {
+                config.rename_repo(&key, &name);
+                config.save_changes()?;
+                inform(cli.quiet, format!("Renamed repository '{}' to '{}'", key, name));
+            } else {
+                config.save_changes()?;
+                inform(cli.quiet, format!("Added repository '{}'", name));
+            }
+        }
+        Some(Commands::Export { out, paths }) => {
+            let mut repos: Vec<_> = config
+                .repos
+                .iter()
+                .map(|(name, entry)| ExportedRepo {
+                    name: name.clone(),
+                    // Non-UTF-8 paths wouldn't mean much on another machine anyway
+                    path: paths
+                        .then(|| entry.path.clone())
+                        .filter(|path| path.to_str().is_some()),
+                    remote: entry.remote.clone(),
+                })
+                .collect();
+            repos.sort_by(|a, b| a.name.cmp(&b.name));
+            let contents = serde_json::to_string_pretty(&ExportedIndex { repos })
+                .map_err(|e| GcdError::Io(format!("failed to serialize repositories: {}", e)))?;
+            match out {
+                Some(out) => std::fs::write(&out, contents + "\n")
+                    .map_err(|e| GcdError::Io(format!("failed to write '{}': {}", out.display(), e)))?,
+                None => println!("{}", contents),
+            }
+        }
+        Some(Commands::Import {
+            from: Some(source), ..
+        }) => {
+            let (program, args) = source.command();
+            let stdout =
+                command_stdout(Command::new(program).args(args)).map_err(|e| GcdError::Io(e.to_string()))?;
+            // Tools like zoxide track plenty of directories that aren't
+            // repositories; leave those out without reporting each one
+            let lines = String::from_utf8_lossy(&stdout)
+                .lines()
+                .filter(|line| {
+                    let path = Path::new(line.trim());
+                    is_git_repo(path) || is_bare_repo(path)
+                })
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            let options = ScanOptions::from_config(&config, &cli);
+            let count = import_repo_paths(&mut config, lines.into_iter(), &options).len();
+            config.save_changes()?;
+            inform(cli.quiet, format!("Imported {} repositories successfully", count));
+        }
+        Some(Commands::Import { file, rewrite, .. }) => {
+            let file = file.ok_or_else(|| GcdError::Usage("import needs a file or --from".to_string()))?;
+            let contents = std::fs::read_to_string(&file)
+                .map_err(|e| GcdError::Io(format!("cannot read '{}': {}", file.display(), e)))?;
+            let index: ExportedIndex = serde_json::from_str(&contents)
+                .map_err(|e| GcdError::Io(format!("cannot parse '{}': {}", file.display(), e)))?;
+            let count = import_exported(&mut config, index.repos, &rewrite);
+            config.save_changes()?;
+            inform(cli.quiet, format!("Imported {} repositories successfully", count));
+        }
+        Some(Commands::Rename { old, new, force }) => {
+            if !config.repos.contains_key(&old) {
+                return Err(GcdError::NoMatch(format!("No such repository '{}'", old)));
+            }
+            if old != new && config.repos.contains_key(&new) && !force {
+                return Err(GcdError::Usage(format!(
+                    "repository '{}' already exists (use --force to overwrite)",
+                    new
+                )));
+            }
+            config.rename_repo(&old, &new);
+            config.save_changes()?;
+            inform(cli.quiet, format!("Renamed repository '{}' to '{}'", old, new));
+        }
+        Some(Commands::Reindex { full, branches }) => {
+            if config.scan_paths.is_empty() {
+                return Err(GcdError::Usage(
+                    "no directories have been indexed yet; run `gcd index <path>` first".to_string(),
+                ));
+            }
+            let paths = config.scan_paths.clone();
+            let mut options = ScanOptions::from_config(&config, &cli);
+            options.full = full;
+            options.branches |= branches;
+            let count = index_paths(&mut config, &paths, &options)?.len();
+            config.save_changes()?;
+            inform(cli.quiet, format!("Reindexed {} repositories successfully", count));
+        }
+        Some(Commands::Clean { dry_run, older_than }) => {
+            let cutoff = older_than
+                .map(|age| SystemTime::now().checked_sub(age).unwrap_or(unknown_time()));
+            let mut stale: Vec<_> = config
+                .repos
+                .iter()
+                .filter(|(name, entry)| {
+                    // Repositories that were never visited count as stale once a cutoff is given
+                    let unvisited = cutoff.is_some_and(|cutoff| {
+                        config.last_visited.get(*name).is_none_or(|time| *time < cutoff)
+                    });
+                    unvisited || !entry.path.exists()
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            stale.sort();
+
+            if dry_run || !cli.quiet {
+                for name in &stale {
+                    println!("{}: {}", name, config.repos[name].path.display());
+                }
+            }
+            if dry_run {
+                println!("Would prune {} stale repositories", stale.len());
+            } else {
+                for name in &stale {
+                    config.remove_repo(name);
+                }
+                config.save_changes()?;
+                inform(cli.quiet, format!("Pruned {} stale repositories", stale.len()));
+            }
+        }
+        Some(Commands::PruneDuplicates { keep, dry_run }) => {
+            if keep.is_none() && !dry_run && !std::io::stdin().is_terminal() {
+                return Err(GcdError::Usage(
+                    "pass --keep to choose names when not running interactively".to_string(),
+                ));
+            }
+            let groups = duplicate_repos(&config);
+            let mut merged = 0;
+            for (path, names) in groups {
+                if dry_run {
+                    println!("{}: {}", path.display(), names.join(", "));
+                    merged += names.len() - 1;
+                    continue;
+                }
+                let Some(kept) = name_to_keep(&config, &path, &names, keep) else {
+                    eprintln!("Invalid selection; leaving {} as it is", path.display());
+                    continue;
+                };
+                for name in names.iter().filter(|name| **name != kept) {
+                    config.merge_repo(name, &kept);
+                    inform(cli.quiet, format!("Merged '{}' into '{}'", name, kept));
+                    merged += 1;
+                }
+            }
+            if dry_run {
+                println!("Would merge {} duplicate entries", merged);
+            } else {
+                if merged > 0 {
+                    config.save_changes()?;
+                }
+                inform(cli.quiet, format!("Merged {} duplicate entries", merged));
+            }
+        }
+        Some(Commands::Root) => match git_toplevel() {
+            Some(root) => emit_destination(&cli, &root, None),
+            None => return Err(GcdError::NoMatch("Not inside a git repository".to_string())),
+        },
+        Some(Commands::ConfigPath) | Some(Commands::Doctor) | Some(Commands::Capabilities) => {
+            unreachable!("handled before the config is loaded")
+        }
+        Some(Commands::Bench {
+            target: BenchTarget::Index { path, threads, full },
+        }) => {
+            let path = path
+                .canonicalize()
+                .map_err(|e| GcdError::Io(format!("cannot scan '{}': {}", path.display(), e)))?;
+            let mut options = ScanOptions::from_config(&config, &cli);
+            options.threads = threads;
+            options.full = full;
+            let start = std::time::Instant::now();
+            let discovery = find_git_repos(&path, &options, &config.scan_cache);
+            let elapsed = start.elapsed();
+
+            eprintln!("Scanned {}", path.display());
+            eprintln!("  time:         {:.3}s", elapsed.as_secs_f64());
+            eprintln!("  directories:  {}", discovery.visited);
+            eprintln!("  repositories: {}", discovery.changed.len() + discovery.unchanged.len());
+            eprintln!("    unchanged:  {}", discovery.unchanged.len());
+        }
+        Some(Commands::Stats) => {
+            let mut visits: Vec<_> = config.visits.iter().collect();
+            visits.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (name, count) in visits {
+                println!("{:>6}  {}", count, name);
+            }
+        }
+        Some(Commands::Completions { shell }) => {
+            print_completions(&shell)?;
+        }
+        Some(Commands::Install {
+            shell,
+            mode,
+            yes,
+            command_name,
+        }) => {
+            let shell = match shell {
+                Some(shell) => shell,
+                None => match detect_shell() {
+                    Some(shell) => {
+                        inform(cli.quiet, format!("Detected shell: {}", shell));
+                        shell
+                    }
+                    None => {
+                        inform(cli.quiet, "Could not detect your shell; defaulting to bash");
+                        "bash".to_string()
+                    }
+                },
+            };
+            let confirm = |action: &str| confirm_rc_change(action, yes, cli.quiet);
+            match install_shell_integration(&shell, mode, &command_name, confirm)? {
+                IntegrationChange::Installed => {
+                    inform(cli.quiet, format!("Shell integration installed for {}", shell))
+                }
+                IntegrationChange::Updated => {
+                    inform(cli.quiet, format!("Shell integration updated for {}", shell))
+                }
+                IntegrationChange::Unchanged | IntegrationChange::Removed => {
+                    let message = format!("Shell integration already up to date for {}", shell);
+                    inform(cli.quiet, message)
+                }
+                IntegrationChange::Declined => inform(cli.quiet, "Left the rc file unchanged"),
+            }
+        }
+        Some(Commands::Init {
+            shell,
+            mode,
+            command_name,
+        }) => print!("{}", render_integration(&shell, mode, &command_name)?),
+        Some(Commands::Uninstall { shell, yes }) => {
+            let confirm = |action: &str| confirm_rc_change(action, yes, cli.quiet);
+            match uninstall_shell_integration(&shell, confirm)? {
+                IntegrationChange::Declined => inform(cli.quiet, "Left the rc file unchanged"),
+                IntegrationChange::Unchanged => {
+                    inform(cli.quiet, format!("No shell integration installed for {}", shell))
+                }
+                _ => inform(cli.quiet, format!("Shell integration removed for {}", shell)),
+            }
+        }
+        Some(Commands::List { filter, sort, format }) => {
+            // On stderr so that the listing itself stays easy to parse
+            if !cli.quiet {
+                eprintln!("Profile: {}", config.profile_name());
+            }
+            let mut repos: Vec<_> = tagged_repos(&cli, &config)
+                .filter(|(name, _)| filter.as_ref().is_none_or(|f| name.contains(f.as_str())))
+                .collect();
+            match sort {
+                ListSort::Name => repos.sort_by(|a, b| a.0.cmp(b.0)),
+                ListSort::Path => repos.sort_by(|a, b| a.1.path.cmp(&b.1.path)),
+                ListSort::Visits => repos.sort_by(|a, b| {
+                    let visits_a = config.visits.get(a.0).copied().unwrap_or(0);
+                    let visits_b = config.visits.get(b.0).copied().unwrap_or(0);
+                    visits_b.cmp(&visits_a).then_with(|| a.0.cmp(b.0))
+                }),
+                ListSort::Added => {
+                    repos.sort_by(|a, b| b.1.added_at.cmp(&a.1.added_at).then_with(|| a.0.cmp(b.0)))
+                }
+            }
+            if let Some(limit) = cli.limit {
+                repos.truncate(limit);
+            }
+            if let Some(format) = format {
+                for (name, entry) in repos {
+                    let visits = config.visits.get(name).copied().unwrap_or(0);
+                    let tags = config.tags.get(name).map_or(&[][..], Vec::as_slice);
+                    let path = shown_path(&cli, &entry.path);
+                    emit(cli.print0, format.render(name, entry, &path, visits, tags));
+                }
+                return Ok(());
+            }
+            let color = color_enabled(std::io::stdout().is_terminal());
+            for (name, entry) in repos {
+                let tags = match config.tags.get(name).filter(|tags| !tags.is_empty()) {
+                    Some(tags) => format!(" [{}]", tags.join(", ")),
+                    None => String::new(),
+                };
+                let name = match filter.as_deref().filter(|_| color) {
+                    Some(f) => {
+                        let start = name.find(f).map_or(0, |at| name[..at].chars().count());
+                        let indices: Vec<_> = (start..start + f.chars().count()).collect();
+                        highlight(name, &indices)
+                    }
+                    None => name.to_string(),
+                };
+                let source = match entry.source_root.as_deref().filter(|_| cli.verbose) {
+                    Some(root) => format!(" (from {})", root.display()),
+                    None => String::new(),
+                };
+                let path = shown_path(&cli, &entry.path);
+                emit(cli.print0, format!("{}{}: {}{}", name, tags, path.display(), source));
+            }
+
+            let mut aliases: Vec<_> = config
+                .aliases
+                .iter()
+                .filter(|(alias, _)| filter.as_ref().is_none_or(|f| alias.contains(f.as_str())))
+                .filter(|(_, target)| config.has_tag(target, cli.tag.as_deref()))
+                .collect();
+            aliases.sort();
+            for (alias, target) in aliases {
+                if let Some(entry) = config.repos.get(target) {
+                    let path = shown_path(&cli, &entry.path);
+                    let record = format!("{} (alias of {}): {}", alias, target, path.display());
+                    emit(cli.print0, record);
+                }
+            }
+        }
+        Some(Commands::Alias { alias, target }) => {
+            if !config.repos.contains_key(&target) {
+                return Err(GcdError::NoMatch(format!("No such repository '{}'", target)));
+            }
+            config.aliases.insert(alias.clone(), target.clone());
+            config.save_changes()?;
+            inform(cli.quiet, format!("Aliased '{}' to '{}'", alias, target));
+        }
+        Some(Commands::Tag { name, tags, remove }) => {
+            if !config.repos.contains_key(&name) {
+                return Err(GcdError::NoMatch(format!("No such repository '{}'", name)));
+            }
+            if tags.is_empty() {
+                for tag in config.tags.get(&name).into_iter().flatten() {
+                    emit(cli.print0, tag);
+                }
+                return Ok(());
+            }
+            let current = config.tags.entry(name.clone()).or_default();
+            if remove {
+                current.retain(|tag| !tags.contains(tag));
+            } else {
+                current.extend(tags);
+                current.sort();
+                current.dedup();
+            }
+            let summary = format!("Tags of '{}': {}", name, current.join(", "));
+            if current.is_empty() {
+                config.tags.remove(&name);
+            }
+            config.save_changes()?;
+            inform(cli.quiet, summary);
+        }
+        Some(Commands::Which { pattern, all }) => {
+            let (pattern, worktree) = split_worktree(&config, &pattern);
+            let (pattern, subpath) = split_subpath(&cli, &config, pattern, &match_options);
+            let matches = resolve_matches(&cli, &config, pattern, &match_options)?;
+            if matches.is_empty() {
+                return Err(GcdError::NoMatch("No matching repository found".to_string()));
+            }
+            if all {
+                for (score, name, path) in &matches {
+                    emit(cli.print0, format!("{:>6}  {}: {}", score, name, shown_path(&cli, path).display()));
+                }
+            } else {
+                let (_, name, path) = matches[0];
+                let root = worktree_root(name, path, worktree, &match_options)?;
+                emit_path(cli.print0, &shown_path(&cli, &enter_subpath(name, &root, subpath)));
+            }
+        }
+        Some(Commands::Open { pattern }) => {
+            let (pattern, worktree) = split_worktree(&config, &pattern);
+            let (pattern, subpath) = split_subpath(&cli, &config, pattern, &match_options);
+            let matches = resolve_matches(&cli, &config, pattern, &match_options)?;
+            let Some(&(_, name, path)) = matches.first() else {
+                return Err(GcdError::NoMatch("No matching repository found".to_string()));
+            };
+            let root = worktree_root(name, path, worktree, &match_options)?;
+            let path = enter_subpath(name, &root, subpath);
+            open_repo(config.open_command.as_deref(), &path)
+                .map_err(|e| GcdError::Io(format!("failed to open '{}': {}", path.display(), e)))?;
+        }
+        Some(Commands::Remove { name, yes }) => {
+            let exact = if config.repos.contains_key(&name) {
+                Some(name.clone())
+            } else {
+                config.aliases.get(&name).filter(|target| config.repos.contains_key(*target)).cloned()
+            };
+            let key = match exact {
+                Some(key) => key,
+                None => {
+                    let key = best_matches(&config.repos, &name, &match_options)
+                        .first()
+                        .map(|(_, key, _)| (*key).clone())
+                        .ok_or_else(|| GcdError::NoMatch(format!("No such repository '{}'", name)))?;
+                    if !confirm_removal(&name, &key, yes) {
+                        return Err(GcdError::NoMatch(format!(
+                            "Not removing '{}'; pass its exact name or --yes to remove it",
+                            key
+                        )));
+                    }
+                    key
+                }
+            };
+            config.remove_repo(&key);
+            config.save_changes()?;
+            inform(cli.quiet, format!("Removed repository '{}'", key));
+        }
+        None => {
+            if let Some(name) = cli.resolve_name.take() {
+                let name = if name == "-" {
+                    let mut line = String::new();
+                    std::io::stdin()
+                        .read_line(&mut line)
+                        .map_err(|e| GcdError::Io(format!("failed to read a name from stdin: {}", e)))?;
+                    line
+                } else {
+                    name
+                };
+                let name = name.split('\t').next().unwrap_or_default().trim_end_matches(['\r', '\n']);
+                if name.is_empty() {
+                    // e.g. the picker was cancelled
+                    return Err(GcdError::NoMatch(String::new()));
+                }
+                cli.exact = true;
+                navigate(&cli, &mut config, name, &match_options)?;
+            } else if cli.candidates {
+                let candidates = match &cli.pattern {
+                    Some(pattern) => resolve_matches(&cli, &config, pattern, &match_options)?,
+                    None if cli.branch.is_some() => resolve_matches(&cli, &config, "", &match_options)?,
+                    None => {
+                        let mut repos: Vec<_> =
+                            tagged_repos(&cli, &config).map(|(name, entry)| (0, name, &entry.path)).collect();
+                        repos.sort_by(|a, b| a.1.cmp(b.1));
+                        repos.truncate(cli.limit.unwrap_or(usize::MAX));
+                        repos
+                    }
+                };
+                for (_, name, path) in candidates {
+                    emit(cli.print0, format!("{}\t{}", name, shown_path(&cli, path).display()));
+                }
+            } else if cli.list_names {
+                let mut names: Vec<_> = config.repos.keys().chain(config.aliases.keys()).collect();
+                names.sort();
+                for name in names {
+                    emit(cli.print0, name);
+                }
+            } else if cli.pattern.as_deref() == Some(".") {
+                // `gcd .` jumps to the root of the current repository; outside
+                // of one it lists repositories like a bare `gcd`
+                match git_toplevel() {
+                    Some(root) => emit_destination(&cli, &root, None),
+                    None => list_repos(&cli, &config)?,
+                }
+            } else if cli.pattern.as_deref() == Some("-") {
+                navigate_back(&cli, &mut config)?;
+            } else if let Some(pattern) = &cli.pattern {
+                navigate(&cli, &mut config, pattern, &match_options)?;
+            } else if cli.branch.is_some() {
+                navigate(&cli, &mut config, "", &match_options)?;
+            } else {
+                list_repos(&cli, &config)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> RepoEntry {
+        RepoEntry {
+            path: PathBuf::from(path),
+            indexed_at: unknown_time(),
+            added_at: unknown_time(),
+            default_branch: None,
+            remote: None,
+            source_root: None,
+            current_branch: None,
+        }
+    }
+
+    fn repos(names: &[&str]) -> HashMap<String, RepoEntry> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), entry(&format!("/src/{}", name))))
+            .collect()
+    }
+
+    fn match_options() -> MatchOptions {
+        MatchOptions {
+            match_path: false,
+            min_score: None,
+            case_sensitive: false,
+            matcher: MatcherKind::Skim,
+            weights: FieldWeights {
+                name: 1.0,
+                alias: 1.0,
+                remote: 1.0,
+                path: 1.0,
+            },
+            aliases: HashMap::new(),
+        }
+    }
+
+    fn names<'a>(matches: &[(i64, &'a String, &PathBuf)]) -> Vec<&'a str> {
+        matches.iter().map(|(_, name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn best_matches_puts_the_highest_score_first() {
+        let repos = repos(&["my-api-client", "api", "tapir", "website"]);
+        let matches = best_matches(&repos, "api", &match_options());
+        assert_eq!(names(&matches)[0], "api");
+        assert!(matches.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+        assert!(!names(&matches).contains(&"website"));
+    }
+
+    #[test]
+    fn best_matches_orders_ties_by_name() {
+        // Equal scores; each map iterates in its own random order
+        for _ in 0..20 {
+            let repos = repos(&["api-c", "api-a", "api-b"]);
+            let matches = best_matches(&repos, "api", &match_options());
+            assert_eq!(matches[0].0, matches[2].0);
+            assert_eq!(names(&matches), ["api-a", "api-b", "api-c"]);
+        }
+    }
+
+    #[test]
+    fn best_matches_is_empty_without_a_match() {
+        let repos = repos(&["api", "website"]);
+        assert!(best_matches(&repos, "zzz", &match_options()).is_empty());
+        assert!(best_matches(&HashMap::new(), "api", &match_options()).is_empty());
+    }
+
+    #[test]
+    fn score_repo_does_not_depend_on_earlier_calls() {
+        let shared = SkimMatcherV2::default();
+        let options = match_options();
+        let entry = entry("/src/x");
+        for pattern in ["api", "ai", "pi", "aapi", "a"] {
+            for name in ["tapir", "api-b", "my-api-client", "a", "website"] {
+                let fresh = score_repo(&SkimMatcherV2::default(), name, &entry, pattern, &options);
+                assert_eq!(score_repo(&shared, name, &entry, pattern, &options), fresh);
+            }
+        }
+    }
+
+    /// A directory laid out like a git directory: `HEAD`, `objects/`, `refs/`.
+    fn make_git_dir(path: &Path) {
+        std::fs::create_dir_all(path.join("objects")).unwrap();
+        std::fs::create_dir_all(path.join("refs")).unwrap();
+        std::fs::write(path.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+    }
+
+    #[test]
+    fn detects_a_clone_with_a_git_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        make_git_dir(&dir.path().join(".git"));
+        assert!(is_git_repo(dir.path()));
+        assert!(!is_bare_repo(dir.path()));
+    }
+
+    #[test]
+    fn detects_a_worktree_with_a_git_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".git"), "gitdir: /src/app/.git/worktrees/feature\n").unwrap();
+        assert!(is_git_repo(dir.path()));
+        assert!(!is_bare_repo(dir.path()));
+    }
+
+    #[test]
+    fn detects_a_bare_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let bare = dir.path().join("project.git");
+        make_git_dir(&bare);
+        assert!(is_bare_repo(&bare));
+        assert!(!is_git_repo(&bare));
+    }
+
+    #[test]
+    fn a_git_directory_is_not_a_bare_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = dir.path().join(".git");
+        make_git_dir(&git);
+        assert!(!is_bare_repo(&git));
+    }
+
+    #[test]
+    fn a_plain_directory_is_not_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_git_repo(dir.path()));
+        assert!(!is_bare_repo(dir.path()));
+    }
+
+    #[test]
+    fn initialism_splits_on_separators_and_case_changes() {
+        assert_eq!(initialism("my-python-project"), "mpp");
+        assert_eq!(initialism("my_python_project"), "mpp");
+        assert_eq!(initialism("myPythonProject"), "mPP");
+        assert_eq!(initialism("my.python project"), "mpp");
+        assert_eq!(initialism("api"), "a");
+    }
+
+    #[test]
+    fn initialism_bonus_needs_a_prefix_of_the_initials() {
+        assert_eq!(initialism_bonus("my-python-project", "mpp", false), Some(3 * INITIALISM_BONUS));
+        assert_eq!(initialism_bonus("my_python_project", "mp", false), Some(2 * INITIALISM_BONUS));
+        assert_eq!(initialism_bonus("myPythonProject", "mpp", false), Some(3 * INITIALISM_BONUS));
+        assert_eq!(initialism_bonus("my-python-project", "pp", false), None);
+    }
+
+    #[test]
+    fn initialism_bonus_respects_case_sensitivity() {
+        assert_eq!(initialism_bonus("myPythonProject", "mPP", true), Some(3 * INITIALISM_BONUS));
+        assert_eq!(initialism_bonus("myPythonProject", "mpp", true), None);
+    }
+
+    #[test]
+    fn initialism_bonus_ignores_single_characters() {
+        assert_eq!(initialism_bonus("my-python-project", "m", false), None);
+        assert_eq!(initialism_bonus("my-python-project", "", false), None);
+    }
+
+    fn sample_config() -> Config {
+        let mut config = Config::default();
+        let mut app = entry("/src/app");
+        app.indexed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        app.default_branch = Some("main".to_string());
+        app.remote = Some("github.com/me/app".to_string());
+        app.source_root = Some(PathBuf::from("/src"));
+        app.current_branch = Some("feature/login".to_string());
+        config.repos.insert("app".to_string(), app);
+        config.repos.insert("api".to_string(), entry("/src/api"));
+        config.scan_paths.push(PathBuf::from("/src"));
+        config.visits.insert("app".to_string(), 3);
+        config.last_visited.insert("app".to_string(), SystemTime::UNIX_EPOCH + Duration::from_secs(42));
+        config.aliases.insert("a".to_string(), "app".to_string());
+        config.tags.insert("app".to_string(), vec!["work".to_string()]);
+        config.hooks.insert("api".to_string(), "source .venv/bin/activate".to_string());
+        config.min_score = Some(10);
+        config
+    }
+
+    /// Serialize through `format` and back, comparing the serialized forms.
+    fn assert_round_trips(format: ConfigFormat, config: &Config) {
+        let contents = format.serialize(config).unwrap();
+        let parsed = format.parse(&contents).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(config).unwrap());
+    }
+
+    #[test]
+    fn json_config_round_trips() {
+        assert_round_trips(ConfigFormat::Json, &sample_config());
+    }
+
+    #[test]
+    fn toml_config_round_trips() {
+        assert_round_trips(ConfigFormat::Toml, &sample_config());
+    }
+
+    #[test]
+    fn legacy_bare_path_entries_still_load() {
+        let config = ConfigFormat::Json.parse(r#"{"repos": {"app": "/src/app"}}"#).unwrap();
+        let app = &config.repos["app"];
+        assert_eq!(app.path, Path::new("/src/app"));
+        assert_eq!(app.indexed_at, unknown_time());
+        assert!(app.remote.is_none());
+        // Written back as a full entry
+        assert_round_trips(ConfigFormat::Json, &config);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip_as_bytes() {
+        let mut config = Config::default();
+        let path = path_from_bytes(b"/src/caf\xe9".to_vec());
+        config.repos.insert("cafe".to_string(), entry("/"));
+        config.repos.get_mut("cafe").unwrap().path = path.clone();
+        for format in [ConfigFormat::Json, ConfigFormat::Toml] {
+            let contents = format.serialize(&config).unwrap();
+            assert_eq!(format.parse(&contents).unwrap().repos["cafe"].path, path);
+        }
+        let contents = ConfigFormat::Json.serialize(&config).unwrap();
+        assert!(contents.contains("233"), "stored as a byte array: {}", contents);
+    }
+
+    #[test]
+    fn concurrent_visits_add_up() {
+        let base = serde_json::json!({ "visits": { "app": 3, "web": 1 }, "editor": "vi" });
+        let ours = serde_json::json!({ "visits": { "app": 4, "web": 1, "new": 1 }, "editor": "vi" });
+        let theirs = serde_json::json!({ "visits": { "app": 5, "web": 2, "new": 2 }, "editor": "code" });
+        assert_eq!(
+            merge_changes(&base, ours, theirs, false),
+            serde_json::json!({ "visits": { "app": 6, "web": 2, "new": 3 }, "editor": "code" })
+        );
+    }
+
+    #[test]
+    fn finds_the_end_of_blocks_installed_under_another_name() {
+        for shell in SUPPORTED_SHELLS {
+            let template = integration_template(shell).unwrap();
+            let block = format!(
+                "{} v{}\n{}",
+                INTEGRATION_MARKER,
+                INTEGRATION_VERSION,
+                render_integration(shell, IntegrationMode::Cd, "j").unwrap()
+            );
+            let content = format!("export A=1\n{}# after\n", block);
+            let (start, end) = find_integration_block(&content, template).unwrap();
+            assert_eq!(&content[start..end], block, "{}", shell);
+        }
+    }
+
+    #[test]
+    fn tells_submodules_from_worktrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let submodule = dir.path().join("vendor/lib");
+        std::fs::create_dir_all(&submodule).unwrap();
+        std::fs::write(submodule.join(".git"), "gitdir: ../../.git/modules/vendor/lib\n").unwrap();
+        assert!(is_submodule(&submodule));
+
+        let worktree = dir.path().join("feature");
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::write(worktree.join(".git"), "gitdir: /src/app/.git/worktrees/feature\n").unwrap();
+        assert!(!is_submodule(&worktree));
+
+        make_git_dir(&dir.path().join(".git"));
+        assert!(!is_submodule(dir.path()));
+    }
+
+}